@@ -1,67 +1,237 @@
-use std::{thread, time::Duration};
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::Duration,
+};
 
 use crate::{
+    commands,
+    config::Config,
+    history::History,
     input::{Action, InputHandler},
-    widgets::{file_browser, options, prompt_editor},
+    instructions::{self, Instructions},
+    mentions,
+    openai::ContextSection,
+    prompt::SYSTEM_PROMPT,
+    scanner::Filters,
+    templates::{self, Segment, Variables},
+    vim::Mode,
+    watcher::Watcher,
+    widgets::{
+        completion, file_browser, file_finder, history_search, options, preview, prompt_editor,
+        template_picker,
+    },
 };
 
+use anyhow::{bail, Result};
 use crossbeam::channel::{unbounded, Receiver};
-use crossterm::event::{self, Event as CEvent, KeyCode, KeyModifiers};
-use ignore::WalkBuilder;
+use crossterm::event::{self, Event as CEvent, KeyCode};
 
 pub struct App {
     pub selected_widget: Widget,
     pub file_browser_state: file_browser::State,
     pub prompt_editor_state: prompt_editor::State,
     pub options_state: options::State,
+    pub preview_state: preview::State,
+    // open while a template is being chosen and filled in
+    pub template_picker: Option<template_picker::State>,
+    // open while searching the prompt history
+    pub history_search: Option<history_search::State>,
+    // open while finding files by path
+    pub file_finder: Option<file_finder::State>,
+    // completions for the @mention being typed
+    pub completion: Option<completion::State>,
+    // start of the mention whose completions were dismissed with Esc
+    dismissed_completion: Option<usize>,
+    // files included through @mentions by path
+    mentioned: HashMap<String, Mentioned>,
+    pub extra_context: Vec<ContextSection>,
+    // a `/run` command whose output isn't in the context yet
+    pub running: Option<commands::RunningCommand>,
+    pub config: Config,
+    pub roots: Vec<PathBuf>,
+    // the system prompt is edited like the prompt, in an editor shown in its place
+    pub system_editor_state: prompt_editor::State,
+    pub editing_system_prompt: bool,
+    // the persona the system prompt came from, None for the default or a custom prompt
+    pub persona: Option<String>,
+    pub history: History,
+    // instruction files found for the project, sent unless turned off for the session
+    pub instructions: Vec<Instructions>,
+    pub instructions_enabled: bool,
+    // message shown in the options bar, e.g. when something failed
+    pub status: Option<String>,
+    // keeps the tree in step with the disk while the ui runs
+    watcher: Option<Watcher>,
+    // the file browser's revision the rest of the app has caught up with
+    tree_revision: usize,
 }
 
 impl InputHandler for App {
     fn process_key(&mut self, input: crossterm::event::KeyEvent) -> Option<Action> {
-        // switch widget if control + arrow key was pressed
-        if input.modifiers.contains(KeyModifiers::CONTROL) {
-            match input.code {
-                KeyCode::Char('h') => {
-                    if let Widget::PromptEditor = self.selected_widget {
-                        self.selected_widget = Widget::FileBrowser;
-                    }
+        if let Some(picker) = &mut self.template_picker {
+            match picker.process_key(input) {
+                Ok(template_picker::Outcome::Pending) => {}
+                Ok(template_picker::Outcome::Cancelled) => self.template_picker = None,
+                Ok(template_picker::Outcome::Insert(segments, answers)) => {
+                    self.template_picker = None;
+                    self.insert_template(&segments, &answers);
                 }
-                KeyCode::Char('l') => {
-                    if let Widget::FileBrowser = self.selected_widget {
-                        self.selected_widget = Widget::PromptEditor;
-                    }
-                }
-                KeyCode::Char('k') => {
-                    if let Widget::Options = self.selected_widget {
-                        self.selected_widget = Widget::PromptEditor;
-                    }
+                Err(err) => {
+                    self.template_picker = None;
+                    self.status = Some(format!("{:#}", err));
                 }
-                KeyCode::Char('j') => {
-                    self.selected_widget = match self.selected_widget {
-                        Widget::PromptEditor | Widget::FileBrowser => Widget::Options,
-                        _ => self.selected_widget.clone(),
-                    };
+            }
+            return None;
+        }
+        if let Some(search) = &mut self.history_search {
+            match search.process_key(input) {
+                history_search::Outcome::Pending => {}
+                history_search::Outcome::Cancelled => self.history_search = None,
+                history_search::Outcome::Accept(prompt) => {
+                    self.history_search = None;
+                    self.prompt_editor_state.set_text(&prompt);
+                    self.prompt_changed();
                 }
-                KeyCode::Char('c') => {
-                    return Some(Action::Quit);
+            }
+            return None;
+        }
+        if let Some(finder) = &mut self.file_finder {
+            match finder.process_key(input, &self.file_browser_state) {
+                file_finder::Outcome::Pending => {}
+                file_finder::Outcome::Cancelled => self.file_finder = None,
+                file_finder::Outcome::Toggle(idx) => self.file_browser_state.toggle_file(idx),
+                file_finder::Outcome::Jump(idx) => {
+                    self.file_finder = None;
+                    self.file_browser_state.reveal(idx);
+                    self.file_browser_state.touch(idx);
+                    self.selected_widget = Widget::FileBrowser;
                 }
-                _ => {}
             }
             return None;
         }
 
+        if self.selected_widget == Widget::PromptEditor
+            && !self.editing_system_prompt
+            && (self.process_completion_key(input)
+                || self.process_history_key(input)
+                || self.process_command_key(input))
+        {
+            return None;
+        }
+
+        let keys = &self.config.keys;
+        if keys.templates.matches(&input) {
+            self.open_template_picker();
+            return None;
+        }
+        if keys.file_finder.matches(&input) {
+            self.file_finder = Some(file_finder::State::new(&self.file_browser_state));
+            return None;
+        }
+        if keys.toggle_instructions.matches(&input) {
+            self.status = Some(self.toggle_instructions());
+            return None;
+        }
+        if keys.system_prompt.matches(&input) {
+            self.editing_system_prompt = !self.editing_system_prompt;
+            self.selected_widget = Widget::PromptEditor;
+            self.completion = None;
+            return None;
+        }
+        // switch widget if one of the focus keys was pressed
+        if keys.focus_left.matches(&input) {
+            if let Widget::PromptEditor | Widget::Preview = self.selected_widget {
+                self.selected_widget = Widget::FileBrowser;
+            }
+        } else if keys.focus_right.matches(&input) {
+            if let Widget::FileBrowser = self.selected_widget {
+                self.selected_widget = Widget::PromptEditor;
+            }
+        } else if keys.focus_up.matches(&input) {
+            self.selected_widget = match self.selected_widget {
+                Widget::Options => Widget::PromptEditor,
+                Widget::PromptEditor if self.config.layout.preview => Widget::Preview,
+                _ => self.selected_widget.clone(),
+            };
+        } else if keys.focus_down.matches(&input) {
+            self.selected_widget = match self.selected_widget {
+                Widget::PromptEditor | Widget::FileBrowser => Widget::Options,
+                Widget::Preview => Widget::PromptEditor,
+                _ => self.selected_widget.clone(),
+            };
+        } else if keys.quit.matches(&input) {
+            return Some(Action::Quit);
+        } else {
+            return match self.selected_widget {
+                Widget::FileBrowser => {
+                    // expanding a directory can load its children
+                    let action = self.file_browser_state.process_key(input);
+                    self.sync_tree();
+                    action
+                }
+                Widget::PromptEditor if self.editing_system_prompt => {
+                    self.system_editor_state.process_key(input)
+                }
+                Widget::PromptEditor => {
+                    let action = self.prompt_editor_state.process_key(input);
+                    self.prompt_changed();
+                    action
+                }
+                Widget::Options => self.options_state.process_key(input),
+                Widget::Preview if keys.select.matches(&input) => {
+                    self.toggle_preview_lines();
+                    None
+                }
+                Widget::Preview => self.preview_state.process_key(input),
+            };
+        }
+        // typing after coming back to the editor is undone separately
+        self.active_editor().seal_history();
+        None
+    }
+
+    fn process_paste(&mut self, text: &str) -> Option<Action> {
+        if let Some(picker) = &mut self.template_picker {
+            picker.process_paste(text);
+            return None;
+        }
+        if let Some(search) = &mut self.history_search {
+            search.process_paste(text);
+            return None;
+        }
+        if let Some(finder) = &mut self.file_finder {
+            finder.process_paste(text, &self.file_browser_state);
+            return None;
+        }
         match self.selected_widget {
-            Widget::FileBrowser => self.file_browser_state.process_key(input),
-            Widget::PromptEditor => self.prompt_editor_state.process_key(input),
-            Widget::Options => self.options_state.process_key(input),
+            Widget::FileBrowser => self.file_browser_state.process_paste(text),
+            Widget::PromptEditor if self.editing_system_prompt => {
+                self.system_editor_state.process_paste(text)
+            }
+            Widget::PromptEditor => {
+                let action = self.prompt_editor_state.process_paste(text);
+                self.prompt_changed();
+                action
+            }
+            Widget::Options => self.options_state.process_paste(text),
+            Widget::Preview => self.preview_state.process_paste(text),
         }
     }
 
     fn process_tick(&mut self) {
+        // the background scan goes on whichever widget is focused
+        self.file_browser_state.process_tick();
+        self.sync_tree();
+        self.finish_command();
         match self.selected_widget {
-            Widget::FileBrowser => self.file_browser_state.process_tick(),
-            Widget::PromptEditor => self.prompt_editor_state.process_tick(),
+            Widget::FileBrowser => {}
+            Widget::PromptEditor => self.active_editor().process_tick(),
             Widget::Options => self.options_state.process_tick(),
+            Widget::Preview => self.preview_state.process_tick(),
         }
     }
 }
@@ -71,61 +241,512 @@ pub enum Widget {
     FileBrowser,
     PromptEditor,
     Options,
+    Preview,
 }
 
 // Event wrapper for crossbeam
 pub enum Event<I> {
     Input(I),
     Tick,
+    // directories whose entries changed on disk
+    FilesChanged(Vec<PathBuf>),
 }
 
 impl App {
-    pub fn new() -> Self {
-        let mut entries = Vec::new();
-
-        // Build an iterator with .gitignore rules enabled
-        // Increase max_depth if you want deeper traversal
-        let walker = WalkBuilder::new(".").standard_filters(true).build();
-
-        for entry in walker.flatten() {
-            let depth = entry.depth();
-            // If the file_type is None, skip
-            let file_type = match entry.file_type() {
-                Some(ft) => ft,
-                None => continue,
+    pub fn new(roots: &[PathBuf], config: Config) -> Self {
+        let mut system_editor_state =
+            prompt_editor::State::new(config.keys.clone(), config.editor.clone());
+        let persona = config.prompt.persona.clone();
+        system_editor_state
+            .set_text(&persona_prompt(&config, persona.as_deref()).unwrap_or_default());
+
+        Self {
+            selected_widget: Widget::PromptEditor,
+            file_browser_state: file_browser::State::load(
+                roots,
+                Filters::new(&config.tree),
+                config.keys.clone(),
+            ),
+            prompt_editor_state: prompt_editor::State::new(
+                config.keys.clone(),
+                config.editor.clone(),
+            ),
+            options_state: options::State::new(config.keys.clone()),
+            preview_state: preview::State::new(config.keys.clone(), config.theme.clone()),
+            template_picker: None,
+            history_search: None,
+            file_finder: None,
+            completion: None,
+            dismissed_completion: None,
+            mentioned: HashMap::new(),
+            extra_context: Vec::new(),
+            running: None,
+            roots: roots.to_vec(),
+            system_editor_state,
+            editing_system_prompt: false,
+            persona,
+            history: roots
+                .first()
+                .map(|root| History::load(root))
+                .unwrap_or_default(),
+            instructions: instructions::discover(roots, &config.instructions.files),
+            instructions_enabled: config.instructions.enabled,
+            config,
+            status: None,
+            watcher: None,
+            tree_revision: 0,
+        }
+    }
+}
+
+impl App {
+    // start watching every directory in the tree, the receiver gets the ones that change
+    pub fn watch_files(&mut self) -> Result<Receiver<Vec<PathBuf>>> {
+        let (watcher, changes) = Watcher::start()?;
+        for entry in &self.file_browser_state.file_list {
+            if !entry.is_file() {
+                watcher.watch(Path::new(&entry.path))?;
+            }
+        }
+        self.file_browser_state.take_added_dirs();
+        self.watcher = Some(watcher);
+        Ok(changes)
+    }
+
+    // list directories again after their entries changed on disk
+    pub fn refresh_dirs(&mut self, dirs: &[PathBuf]) {
+        self.file_browser_state.refresh_dirs(dirs);
+        self.sync_tree();
+    }
+
+    // catch up with entries added to or removed from the tree, which moves their indices
+    fn sync_tree(&mut self) {
+        let revision = self.file_browser_state.revision();
+        if revision == self.tree_revision {
+            return;
+        }
+        self.tree_revision = revision;
+        for dir in self.file_browser_state.take_added_dirs() {
+            if let Some(watcher) = &self.watcher {
+                if let Err(err) = watcher.watch(&dir) {
+                    self.status = Some(format!("{:#}", err));
+                }
+            }
+        }
+        if let Some(finder) = &mut self.file_finder {
+            finder.update_results(&self.file_browser_state);
+        }
+        // mentions of files that weren't scanned yet can be resolved now
+        self.sync_mentions();
+    }
+
+    // the editor shown in the prompt pane
+    pub fn active_editor(&mut self) -> &mut prompt_editor::State {
+        match self.editing_system_prompt {
+            true => &mut self.system_editor_state,
+            false => &mut self.prompt_editor_state,
+        }
+    }
+
+    pub fn system_prompt(&self) -> String {
+        self.system_editor_state.get_query_text()
+    }
+
+    // switch to a persona's system prompt, or back to the default one with None
+    pub fn select_persona(&mut self, persona: Option<String>) -> Result<()> {
+        let Some(text) = persona_prompt(&self.config, persona.as_deref()) else {
+            bail!(
+                "Unknown persona `{}`, the configured ones are: {}",
+                persona.unwrap_or_default(),
+                self.config
+                    .personas
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        };
+        self.system_editor_state.set_text(&text);
+        self.persona = persona;
+        Ok(())
+    }
+
+    // turn the project instructions on or off for this session, returns a message saying which
+    pub fn toggle_instructions(&mut self) -> String {
+        if self.instructions.is_empty() {
+            return format!(
+                "No project instructions found, lope looks for {}",
+                self.config.instructions.files.join(" and ")
+            );
+        }
+        self.instructions_enabled = !self.instructions_enabled;
+        match self.instructions_enabled {
+            true => format!(
+                "Sending project instructions from {}",
+                self.instructions
+                    .iter()
+                    .map(|instructions| instructions.path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            false => "Project instructions are off for this session".to_owned(),
+        }
+    }
+
+    fn open_template_picker(&mut self) {
+        match templates::load(&self.roots[0]) {
+            Ok(list) if list.is_empty() => {
+                self.status = Some(
+                    "No templates found, add them to ~/.config/lope/templates or .lope/templates"
+                        .to_owned(),
+                )
+            }
+            Ok(list) => {
+                self.template_picker =
+                    Some(template_picker::State::new(list, self.config.keys.clone()))
+            }
+            Err(err) => self.status = Some(format!("{:#}", err)),
+        }
+    }
+
+    fn insert_template(&mut self, segments: &[Segment], answers: &[String]) {
+        let file_list = self
+            .file_browser_state
+            .get_included_entries()
+            .iter()
+            .map(|entry| entry.display_path.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let variables = Variables {
+            selection: self.prompt_editor_state.selected_text().to_owned(),
+            file_list,
+            git_branch: self
+                .roots
+                .first()
+                .map(|root| templates::git_branch(root))
+                .unwrap_or_default(),
+        };
+        let text = templates::render(segments, &variables, answers);
+        self.prompt_editor_state.insert_text(&text);
+        self.selected_widget = Widget::PromptEditor;
+        self.status = None;
+        self.prompt_changed();
+    }
+
+    // add the output of a `/run` command to the context once it's done
+    fn finish_command(&mut self) {
+        let Some(running) = &self.running else {
+            return;
+        };
+        let Some(finished) = running.finished() else {
+            return;
+        };
+        let command = running.command.clone();
+        self.running = None;
+        self.status = Some(match finished {
+            Ok((section, status)) => {
+                self.extra_context.push(section);
+                format!(
+                    "`{}` {}, its output was added to the context",
+                    command, status
+                )
+            }
+            Err(err) => format!("{:#}", err),
+        });
+    }
+
+    // keep the completion popup and the files included by @mentions in step with the prompt
+    pub fn prompt_changed(&mut self) {
+        self.update_completion();
+        self.sync_mentions();
+    }
+
+    // navigate or accept completions, returns false if the key wasn't for the popup
+    fn process_completion_key(&mut self, input: crossterm::event::KeyEvent) -> bool {
+        let Some(completion) = &mut self.completion else {
+            return false;
+        };
+        match input.code {
+            KeyCode::Up => completion.select_previous(),
+            KeyCode::Down => completion.select_next(),
+            KeyCode::Esc => {
+                self.dismissed_completion = Some(completion.range.start);
+                self.completion = None;
+            }
+            KeyCode::Tab | KeyCode::Enter => {
+                let Some(candidate) = completion.selected() else {
+                    return false;
+                };
+                let (range, replacement) =
+                    (completion.range.clone(), candidate.replacement.clone());
+                // Enter on something already typed out in full goes to the editor, e.g. to run `/clear`
+                let typed = &self.prompt_editor_state.get_display_text()[range.clone()];
+                if input.code == KeyCode::Enter && typed == replacement.trim_end() {
+                    return false;
+                }
+                self.prompt_editor_state.replace_text(range, &replacement);
+                self.prompt_changed();
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Up on the first row and Down on the last row cycle through earlier prompts
+    fn process_history_key(&mut self, input: crossterm::event::KeyEvent) -> bool {
+        let editor = &self.prompt_editor_state;
+        if !matches!(editor.mode(), None | Some(Mode::Insert)) {
+            return false;
+        }
+        if self.config.keys.history_search.matches(&input) {
+            self.history_search = Some(history_search::State::new(
+                self.history.entries().to_vec(),
+                self.config.keys.clone(),
+            ));
+            return true;
+        }
+        if !input.modifiers.is_empty() {
+            return false;
+        }
+        let recalled = match input.code {
+            KeyCode::Up if editor.on_first_row() => self.history.older(editor.get_display_text()),
+            KeyCode::Down if editor.on_last_row() => self.history.newer(),
+            _ => return false,
+        };
+        match recalled.map(str::to_owned) {
+            Some(prompt) => {
+                self.prompt_editor_state.set_text(&prompt);
+                self.prompt_changed();
+                true
+            }
+            // nothing to recall, move the cursor as usual
+            None => false,
+        }
+    }
+
+    // Enter on a prompt that is a slash command runs it instead of starting a new line
+    fn process_command_key(&mut self, input: crossterm::event::KeyEvent) -> bool {
+        let editor = &self.prompt_editor_state;
+        if input.code != KeyCode::Enter
+            || !input.modifiers.is_empty()
+            || !matches!(editor.mode(), None | Some(Mode::Insert))
+        {
+            return false;
+        }
+        let Some(command) = commands::parse(editor.get_display_text()) else {
+            return false;
+        };
+        match command.and_then(|command| commands::execute(self, command)) {
+            Ok(message) => {
+                self.prompt_editor_state.set_text("");
+                self.prompt_changed();
+                self.status = Some(message);
+            }
+            // keep the text so the command can be fixed
+            Err(err) => self.status = Some(format!("{:#}", err)),
+        }
+        true
+    }
+
+    fn update_completion(&mut self) {
+        let editor = &self.prompt_editor_state;
+        let typing = matches!(editor.mode(), None | Some(Mode::Insert));
+        let text = editor.get_display_text();
+        let cursor = editor.buffer().cursor();
+        // the command name while it is being typed, otherwise the @mention at the cursor
+        let command = text[..cursor]
+            .strip_prefix('/')
+            .filter(|name| !name.contains(char::is_whitespace))
+            .map(|name| (0..cursor, name));
+        let is_command = command.is_some();
+        let completing = command.or_else(|| mentions::at_cursor(text, cursor));
+        let Some((range, query)) = completing.filter(|_| typing) else {
+            self.completion = None;
+            self.dismissed_completion = None;
+            return;
+        };
+        if self.dismissed_completion == Some(range.start) {
+            return;
+        }
+        let candidates = match is_command {
+            true => commands::candidates(query),
+            false => self.file_browser_state.mention_candidates(query),
+        };
+        self.completion =
+            (!candidates.is_empty()).then(|| completion::State::new(range, candidates));
+    }
+
+    // include the lines selected in the preview, or leave them out if they already are
+    fn toggle_preview_lines(&mut self) {
+        let browser = &mut self.file_browser_state;
+        let Some(idx) = browser.selected_idx() else {
+            return;
+        };
+        let Some(lines) = self.preview_state.take_selected_lines() else {
+            return;
+        };
+        let included = browser.toggle_lines(idx, lines.clone());
+        self.status = Some(format!(
+            "{} lines {}-{} of {}",
+            if included { "Included" } else { "Left out" },
+            lines.start(),
+            lines.end(),
+            browser.file_list[idx].display_path
+        ));
+    }
+
+    // include the files mentioned in the prompt, and put back the ones whose mention was
+    // deleted. A mention is only applied when it's typed or its lines change, so lines
+    // chosen in the preview afterwards are kept
+    fn sync_mentions(&mut self) {
+        let mut ranges: HashMap<usize, Option<Vec<RangeInclusive<usize>>>> = HashMap::new();
+        for mention in mentions::parse(self.prompt_editor_state.get_display_text()) {
+            let Some(idx) = self.file_browser_state.find_mention(&mention.path) else {
+                continue;
             };
+            // a mention without a line range includes the whole file
+            let file_ranges = ranges.entry(idx).or_insert_with(|| Some(Vec::new()));
+            match file_ranges {
+                Some(file_ranges) if !mention.lines.is_empty() => {
+                    file_ranges.extend(mention.lines);
+                    file_browser::merge_ranges(file_ranges);
+                }
+                file_ranges => *file_ranges = None,
+            }
+        }
 
-            let is_dir = file_type.is_dir();
-            // Convert to string for TUI display
-            let path = entry.path().display().to_string();
+        let browser = &mut self.file_browser_state;
+        let mut previous = std::mem::take(&mut self.mentioned);
+        for (idx, lines) in ranges {
+            let current = Inclusion::of(&browser.file_list[idx]);
+            let path = browser.file_list[idx].path.clone();
+            let before = match previous.remove(&path) {
+                Some(mentioned) if mentioned.lines == lines => {
+                    self.mentioned.insert(path, mentioned);
+                    continue;
+                }
+                // the lines asked for changed, they replace the ones the mention asked for
+                // before unless the file was changed since
+                Some(mentioned) if mentioned.after == current => mentioned.before,
+                _ => current,
+            };
+            let after = before.mention(lines.as_deref());
+            after.apply(browser, idx);
+            self.mentioned.insert(
+                path,
+                Mentioned {
+                    lines,
+                    before,
+                    after,
+                },
+            );
+        }
+        // the mentions that are gone, their files go back to how they were unless they
+        // were changed since
+        for (path, mentioned) in previous {
+            let Some(idx) = browser
+                .file_list
+                .iter()
+                .position(|entry| entry.path == path)
+            else {
+                continue;
+            };
+            if Inclusion::of(&browser.file_list[idx]) == mentioned.after {
+                mentioned.before.apply(browser, idx);
+            }
+        }
+    }
+}
 
-            entries.push(file_browser::TreeEntry::new(path, depth, is_dir));
+// What an @mention did to a file, so it can be undone once the mention is deleted
+struct Mentioned {
+    // the lines the mention asks for, None for the whole file
+    lines: Option<Vec<RangeInclusive<usize>>>,
+    before: Inclusion,
+    after: Inclusion,
+}
+
+// how much of a file is included
+#[derive(Clone, PartialEq)]
+struct Inclusion {
+    excluded: bool,
+    line_ranges: Vec<RangeInclusive<usize>>,
+}
+
+impl Inclusion {
+    fn of(entry: &file_browser::TreeEntry) -> Self {
+        Self {
+            excluded: entry.excluded,
+            line_ranges: entry.line_ranges.clone(),
         }
+    }
 
+    fn apply(&self, browser: &mut file_browser::State, idx: usize) {
+        browser.file_list[idx].line_ranges = self.line_ranges.clone();
+        browser.set_excluded(idx, self.excluded);
+    }
+
+    // a mention includes an excluded file, or adds its lines to those already included
+    fn mention(&self, lines: Option<&[RangeInclusive<usize>]>) -> Self {
+        let mut line_ranges = match (self.excluded, lines) {
+            (true, lines) => lines.unwrap_or_default().to_vec(),
+            // a file included whole stays whole
+            (false, Some(lines)) if !self.line_ranges.is_empty() => {
+                [self.line_ranges.as_slice(), lines].concat()
+            }
+            (false, _) => self.line_ranges.clone(),
+        };
+        file_browser::merge_ranges(&mut line_ranges);
         Self {
-            selected_widget: Widget::PromptEditor,
-            file_browser_state: file_browser::State::new(entries),
-            prompt_editor_state: prompt_editor::State::default(),
-            options_state: options::State::default(),
+            excluded: false,
+            line_ranges,
         }
     }
 }
 
 impl Default for App {
     fn default() -> Self {
-        Self::new()
+        Self::new(&[PathBuf::from(".")], Config::default())
+    }
+}
+
+// a persona's prompt, or the configured or built-in default without one
+fn persona_prompt(config: &Config, persona: Option<&str>) -> Option<String> {
+    match persona {
+        Some(name) => config.personas.get(name).cloned(),
+        None => Some(
+            config
+                .prompt
+                .system
+                .clone()
+                .unwrap_or_else(|| SYSTEM_PROMPT.to_owned()),
+        ),
+    }
+}
+
+// Stops the input thread from reading the terminal while held, e.g. while an external editor runs
+#[derive(Clone, Default)]
+pub struct InputLock(Arc<Mutex<()>>);
+
+impl InputLock {
+    pub fn pause(&self) -> MutexGuard<'_, ()> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
     }
 }
 
 // Crossbeam channel to capture input events (keyboard)
-pub fn input_events() -> Receiver<Event<CEvent>> {
+// crossterm reads from /dev/tty rather than stdin, so this keeps working when stdin is a pipe
+pub fn input_events(lock: InputLock) -> Receiver<Event<CEvent>> {
     let (tx, rx) = unbounded();
     thread::spawn(move || {
         loop {
-            // Poll for user input
-            if event::poll(Duration::from_millis(50)).unwrap() {
-                if let Ok(ev) = event::read() {
-                    tx.send(Event::Input(ev)).unwrap();
+            {
+                let _reading = lock.pause();
+                // Poll for user input
+                if event::poll(Duration::from_millis(50)).unwrap() {
+                    if let Ok(ev) = event::read() {
+                        tx.send(Event::Input(ev)).unwrap();
+                    }
                 }
             }
             tx.send(Event::Tick).unwrap();
@@ -133,3 +754,71 @@ pub fn input_events() -> Receiver<Event<CEvent>> {
     });
     rx
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_deleting_a_mention_leaves_the_file_out() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), "one\ntwo\n").unwrap();
+        let mut app = App::new(&[dir.path().to_owned()], Config::default());
+        app.file_browser_state.finish_scan();
+        let included = |app: &App| {
+            let idx = app.file_browser_state.find_mention("a.txt").unwrap();
+            !app.file_browser_state.file_list[idx].excluded
+        };
+
+        app.prompt_editor_state.set_text("see @a.txt");
+        app.prompt_changed();
+        assert!(included(&app));
+        app.prompt_editor_state.set_text("see ");
+        app.prompt_changed();
+        assert!(!included(&app));
+    }
+
+    #[test]
+    fn test_mentions_keep_lines_chosen_in_the_preview() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), "1\n2\n3\n4\n5\n").unwrap();
+        let mut app = App::new(&[dir.path().to_owned()], Config::default());
+        app.file_browser_state.finish_scan();
+        let idx = app.file_browser_state.find_mention("a.txt").unwrap();
+        let type_prompt = |app: &mut App, prompt: &str| {
+            app.prompt_editor_state.set_text(prompt);
+            app.prompt_changed();
+            let entry = &app.file_browser_state.file_list[idx];
+            (entry.excluded, entry.line_ranges.clone())
+        };
+
+        // lines chosen in the preview are kept by a mention, which adds its own
+        app.file_browser_state.toggle_lines(idx, 2..=3);
+        assert_eq!(type_prompt(&mut app, "see @a.txt"), (false, vec![2..=3]));
+        assert_eq!(
+            type_prompt(&mut app, "see @a.txt:5"),
+            (false, vec![2..=3, 5..=5])
+        );
+        app.file_browser_state.toggle_lines(idx, 1..=1);
+        assert_eq!(
+            type_prompt(&mut app, "see @a.txt:5 now"),
+            (false, vec![1..=3, 5..=5])
+        );
+        assert_eq!(type_prompt(&mut app, "see"), (false, vec![1..=3, 5..=5]));
+
+        // removing the mention puts back what was there before
+        app.file_browser_state.toggle_file(idx);
+        assert_eq!(type_prompt(&mut app, "see @a.txt:4"), (false, vec![4..=4]));
+        assert_eq!(type_prompt(&mut app, "see"), (true, vec![]));
+        app.file_browser_state.toggle_lines(idx, 2..=2);
+        assert_eq!(
+            type_prompt(&mut app, "see @a.txt:4"),
+            (false, vec![2..=2, 4..=4])
+        );
+        assert_eq!(type_prompt(&mut app, "see"), (false, vec![2..=2]));
+    }
+}
@@ -0,0 +1,576 @@
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+use unicode_width::UnicodeWidthStr;
+
+// tabs are drawn as this many spaces
+pub const TAB_WIDTH: usize = 4;
+
+// edits further apart than this are undone separately
+const UNDO_GROUP_TIMEOUT: Duration = Duration::from_secs(1);
+const UNDO_LIMIT: usize = 200;
+
+// An editable piece of text with a cursor and an optional selection.
+// Positions are byte offsets into the text and always sit on grapheme boundaries,
+// so emoji and other multi-codepoint characters are never split.
+#[derive(Default, Clone)]
+pub struct TextBuffer {
+    text: String,
+    cursor: usize,
+    // the other end of the selection, the cursor being the end that moves
+    anchor: Option<usize>,
+    // display column vertical movement tries to keep
+    preferred_column: Option<usize>,
+    // spans edited as a whole, sorted by position
+    atoms: Vec<Atom>,
+}
+
+// A span of the text that is moved over and deleted as a unit, e.g. the marker standing in
+// for a pasted block. `id` says what it stands for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Atom {
+    pub range: Range<usize>,
+    pub id: usize,
+}
+
+// One row of wrapped text, `hard` rows end at a newline or the end of the text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Row {
+    pub start: usize,
+    pub end: usize,
+    pub hard: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+impl TextBuffer {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+            cursor: text.len(),
+            ..Default::default()
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn atoms(&self) -> &[Atom] {
+        &self.atoms
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_owned();
+        self.cursor = text.len();
+        self.anchor = None;
+        self.preferred_column = None;
+        self.atoms.clear();
+    }
+
+    pub fn selection(&self) -> Option<Range<usize>> {
+        let anchor = self.anchor?;
+        if anchor == self.cursor {
+            return None;
+        }
+        Some(anchor.min(self.cursor)..anchor.max(self.cursor))
+    }
+
+    pub fn selected_text(&self) -> Option<&str> {
+        self.selection().map(|range| &self.text[range])
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.anchor = None;
+    }
+
+    pub fn select_all(&mut self) {
+        self.anchor = Some(0);
+        self.cursor = self.text.len();
+    }
+
+    // Every edit goes through here, returns the text that was removed.
+    // Atoms the range reaches into are removed whole and text isn't inserted inside one
+    pub fn replace_range(&mut self, range: Range<usize>, replacement: &str) -> String {
+        let mut range = range;
+        for atom in &self.atoms {
+            if range.is_empty() {
+                if atom.range.start < range.start && range.start < atom.range.end {
+                    range = atom.range.end..atom.range.end;
+                }
+            } else if atom.range.start < range.end && range.start < atom.range.end {
+                range = range.start.min(atom.range.start)..range.end.max(atom.range.end);
+            }
+        }
+        let removed = self.text[range.clone()].to_owned();
+        self.text.replace_range(range.clone(), replacement);
+        // atoms after the edit move with the text, those it covered are gone
+        let removed_len = range.len();
+        self.atoms.retain_mut(|atom| {
+            if atom.range.start >= range.end {
+                atom.range.start = atom.range.start - removed_len + replacement.len();
+                atom.range.end = atom.range.end - removed_len + replacement.len();
+                true
+            } else {
+                atom.range.end <= range.start
+            }
+        });
+        self.cursor = range.start + replacement.len();
+        self.anchor = None;
+        self.preferred_column = None;
+        removed
+    }
+
+    // insert at the cursor, replacing the selection if there is one
+    pub fn insert_str(&mut self, s: &str) {
+        let range = self.selection().unwrap_or(self.cursor..self.cursor);
+        self.replace_range(range, s);
+    }
+
+    // insert `s` like insert_str, as an atom standing for `id`
+    pub fn insert_atom(&mut self, s: &str, id: usize) {
+        self.insert_str(s);
+        let range = self.cursor - s.len()..self.cursor;
+        let idx = self
+            .atoms
+            .partition_point(|atom| atom.range.start < range.start);
+        self.atoms.insert(idx, Atom { range, id });
+    }
+
+    pub fn backspace(&mut self) {
+        let range = match self.selection() {
+            Some(range) => range,
+            None => self.prev_boundary(self.cursor)..self.cursor,
+        };
+        self.replace_range(range, "");
+    }
+
+    pub fn delete(&mut self) {
+        let range = match self.selection() {
+            Some(range) => range,
+            None => self.cursor..self.next_boundary(self.cursor),
+        };
+        self.replace_range(range, "");
+    }
+
+    pub fn delete_word_back(&mut self) {
+        let range = match self.selection() {
+            Some(range) => range,
+            None => self.word_start_before(self.cursor)..self.cursor,
+        };
+        self.replace_range(range, "");
+    }
+
+    // move the cursor, extending the selection from where it was when `select` is set
+    pub fn move_to(&mut self, pos: usize, select: bool) {
+        if select {
+            if self.anchor.is_none() {
+                self.anchor = Some(self.cursor);
+            }
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = pos.min(self.text.len());
+        self.preferred_column = None;
+    }
+
+    pub fn move_left(&mut self, select: bool) {
+        let pos = match (self.selection(), select) {
+            (Some(range), false) => range.start,
+            _ => self.prev_boundary(self.cursor),
+        };
+        self.move_to(pos, select);
+    }
+
+    pub fn move_right(&mut self, select: bool) {
+        let pos = match (self.selection(), select) {
+            (Some(range), false) => range.end,
+            _ => self.next_boundary(self.cursor),
+        };
+        self.move_to(pos, select);
+    }
+
+    pub fn move_word_left(&mut self, select: bool) {
+        self.move_to(self.word_start_before(self.cursor), select);
+    }
+
+    pub fn move_word_right(&mut self, select: bool) {
+        self.move_to(self.word_start_after(self.cursor), select);
+    }
+
+    pub fn move_line_start(&mut self, select: bool) {
+        self.move_to(self.line_start(self.cursor), select);
+    }
+
+    pub fn move_line_end(&mut self, select: bool) {
+        self.move_to(self.line_end(self.cursor), select);
+    }
+
+    // move by `delta` wrapped rows, keeping the display column where possible
+    pub fn move_rows(&mut self, delta: isize, width: usize, select: bool) {
+        let rows = self.wrap(width);
+        let (row, column) = self.cursor_row_column(&rows);
+        let column = self.preferred_column.unwrap_or(column);
+        let target = (row as isize + delta).clamp(0, rows.len() as isize - 1) as usize;
+
+        let pos = if target == row && delta < 0 {
+            0
+        } else if target == row && delta > 0 {
+            self.text.len()
+        } else {
+            self.offset_at_column(rows[target], column)
+        };
+        self.move_to(pos, select);
+        self.preferred_column = Some(column);
+    }
+
+    pub fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos].rfind('\n').map(|idx| idx + 1).unwrap_or(0)
+    }
+
+    pub fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .find('\n')
+            .map(|idx| pos + idx)
+            .unwrap_or(self.text.len())
+    }
+
+    // atoms count as a single grapheme
+    pub fn prev_boundary(&self, pos: usize) -> usize {
+        if let Some(atom) = self
+            .atoms
+            .iter()
+            .find(|atom| atom.range.start < pos && pos <= atom.range.end)
+        {
+            return atom.range.start;
+        }
+        let mut cursor = GraphemeCursor::new(pos, self.text.len(), true);
+        cursor
+            .prev_boundary(&self.text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(0)
+    }
+
+    pub fn next_boundary(&self, pos: usize) -> usize {
+        if let Some(atom) = self
+            .atoms
+            .iter()
+            .find(|atom| atom.range.start <= pos && pos < atom.range.end)
+        {
+            return atom.range.end;
+        }
+        let mut cursor = GraphemeCursor::new(pos, self.text.len(), true);
+        cursor
+            .next_boundary(&self.text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(self.text.len())
+    }
+
+    // start of the word before `pos`, skipping any whitespace in between
+    pub fn word_start_before(&self, pos: usize) -> usize {
+        let mut pos = pos;
+        while pos > 0 && self.class_before(pos) == CharClass::Whitespace {
+            pos = self.prev_boundary(pos);
+        }
+        if pos == 0 {
+            return 0;
+        }
+        let class = self.class_before(pos);
+        while pos > 0 && self.class_before(pos) == class {
+            pos = self.prev_boundary(pos);
+        }
+        pos
+    }
+
+    // start of the next word after `pos`
+    pub fn word_start_after(&self, pos: usize) -> usize {
+        let len = self.text.len();
+        let mut pos = pos;
+        if pos < len {
+            let class = self.class_at(pos);
+            while pos < len && self.class_at(pos) == class && class != CharClass::Whitespace {
+                pos = self.next_boundary(pos);
+            }
+        }
+        while pos < len && self.class_at(pos) == CharClass::Whitespace {
+            pos = self.next_boundary(pos);
+        }
+        pos
+    }
+
+    fn class_at(&self, pos: usize) -> CharClass {
+        char_class(&self.text[pos..self.next_boundary(pos)])
+    }
+
+    fn class_before(&self, pos: usize) -> CharClass {
+        char_class(&self.text[self.prev_boundary(pos)..pos])
+    }
+
+    // Split the text into rows no wider than `width` columns, breaking after
+    // whitespace where possible and between graphemes otherwise
+    pub fn wrap(&self, width: usize) -> Vec<Row> {
+        let width = width.max(1);
+        let mut rows = Vec::new();
+        let mut line_start = 0;
+
+        for line in self.text.split('\n') {
+            let line_end = line_start + line.len();
+            let mut row_start = line_start;
+            let mut row_width = 0;
+            // last place the current row could be broken after whitespace
+            let mut break_at: Option<(usize, usize)> = None;
+
+            for (idx, grapheme) in line.grapheme_indices(true) {
+                let pos = line_start + idx;
+                let grapheme_width = display_width(grapheme);
+                if row_width + grapheme_width > width && pos > row_start {
+                    let (end, carried) = match break_at {
+                        Some((end, width_at_break)) => (end, row_width - width_at_break),
+                        None => (pos, 0),
+                    };
+                    rows.push(Row {
+                        start: row_start,
+                        end,
+                        hard: false,
+                    });
+                    row_start = end;
+                    row_width = carried;
+                    break_at = None;
+                }
+                row_width += grapheme_width;
+                if grapheme.chars().all(char::is_whitespace) {
+                    break_at = Some((pos + grapheme.len(), row_width));
+                }
+            }
+
+            rows.push(Row {
+                start: row_start,
+                end: line_end,
+                hard: true,
+            });
+            line_start = line_end + 1;
+        }
+        rows
+    }
+
+    // the wrapped row the cursor is on and its display column within it
+    pub fn cursor_row_column(&self, rows: &[Row]) -> (usize, usize) {
+        let row = rows
+            .iter()
+            .position(|row| {
+                self.cursor >= row.start
+                    && (self.cursor < row.end || (self.cursor == row.end && row.hard))
+            })
+            .unwrap_or(rows.len().saturating_sub(1));
+        let start = rows.get(row).map(|row| row.start).unwrap_or(0);
+        (row, display_width(&self.text[start..self.cursor]))
+    }
+
+    fn offset_at_column(&self, row: Row, column: usize) -> usize {
+        let mut width = 0;
+        for (idx, grapheme) in self.text[row.start..row.end].grapheme_indices(true) {
+            let grapheme_width = display_width(grapheme);
+            if width + grapheme_width > column {
+                return row.start + idx;
+            }
+            width += grapheme_width;
+        }
+        // soft wrapped rows continue on the next row, so stop before the last grapheme
+        if row.hard || row.start == row.end {
+            row.end
+        } else {
+            self.prev_boundary(row.end)
+        }
+    }
+}
+
+// The state of a buffer before an undoable change
+#[derive(Clone)]
+struct Snapshot {
+    text: String,
+    cursor: usize,
+    atoms: Vec<Atom>,
+}
+
+// What kind of change an edit was, consecutive edits of the same kind are undone together
+#[derive(Clone, Copy, PartialEq)]
+pub enum EditKind {
+    Typing,
+    Whitespace,
+    Deleting,
+    // pastes, newlines and replacements are always undone on their own
+    Other,
+}
+
+// Undo and redo history for a TextBuffer
+#[derive(Default)]
+pub struct UndoStack {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    last_edit: Option<(EditKind, Instant)>,
+}
+
+impl UndoStack {
+    // Apply `edit` to the buffer, recording the previous state if it changed anything
+    pub fn edit(
+        &mut self,
+        buffer: &mut TextBuffer,
+        kind: EditKind,
+        edit: impl FnOnce(&mut TextBuffer),
+    ) {
+        let before = Snapshot {
+            text: buffer.text.clone(),
+            cursor: buffer.cursor,
+            atoms: buffer.atoms.clone(),
+        };
+        edit(buffer);
+        if buffer.text == before.text {
+            return;
+        }
+
+        let now = Instant::now();
+        // typing a word and the whitespace after it form one group, the next word starts another
+        let merges = match self.last_edit {
+            Some((last, at)) if now.duration_since(at) < UNDO_GROUP_TIMEOUT => {
+                kind != EditKind::Other
+                    && (last == kind || (last == EditKind::Typing && kind == EditKind::Whitespace))
+            }
+            _ => false,
+        };
+        if !merges {
+            self.undo.push(before);
+            if self.undo.len() > UNDO_LIMIT {
+                self.undo.remove(0);
+            }
+        }
+        self.redo.clear();
+        self.last_edit = Some((kind, now));
+    }
+
+    // make the next edit start a new group, e.g. after the cursor moved
+    pub fn seal(&mut self) {
+        self.last_edit = None;
+    }
+
+    pub fn undo(&mut self, buffer: &mut TextBuffer) {
+        Self::swap(&mut self.undo, &mut self.redo, buffer);
+        self.seal();
+    }
+
+    pub fn redo(&mut self, buffer: &mut TextBuffer) {
+        Self::swap(&mut self.redo, &mut self.undo, buffer);
+        self.seal();
+    }
+
+    // restore the last state from `from`, saving the current one onto `to`
+    fn swap(from: &mut Vec<Snapshot>, to: &mut Vec<Snapshot>, buffer: &mut TextBuffer) {
+        if let Some(snapshot) = from.pop() {
+            to.push(Snapshot {
+                text: std::mem::take(&mut buffer.text),
+                cursor: buffer.cursor,
+                atoms: std::mem::take(&mut buffer.atoms),
+            });
+            buffer.set_text(&snapshot.text);
+            buffer.cursor = snapshot.cursor;
+            buffer.atoms = snapshot.atoms;
+        }
+    }
+}
+
+fn char_class(grapheme: &str) -> CharClass {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => CharClass::Whitespace,
+        Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+        _ => CharClass::Punctuation,
+    }
+}
+
+// width of text in terminal columns, counting tabs as TAB_WIDTH
+pub fn display_width(s: &str) -> usize {
+    s.split('\t').map(UnicodeWidthStr::width).sum::<usize>() + s.matches('\t').count() * TAB_WIDTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graphemes_are_never_split() {
+        let mut buffer = TextBuffer::new("a👩‍👩‍👧b");
+        buffer.move_left(false);
+        buffer.backspace();
+        assert_eq!(buffer.text(), "ab");
+
+        let mut buffer = TextBuffer::new("漢字");
+        buffer.move_to(0, false);
+        buffer.move_right(false);
+        buffer.insert_str("x");
+        assert_eq!(buffer.text(), "漢x字");
+    }
+
+    #[test]
+    fn test_selection_is_replaced() {
+        let mut buffer = TextBuffer::new("hello world");
+        buffer.move_word_left(false);
+        buffer.move_line_end(true);
+        assert_eq!(buffer.selected_text(), Some("world"));
+        buffer.insert_str("there");
+        assert_eq!(buffer.text(), "hello there");
+    }
+
+    #[test]
+    fn test_wrap_breaks_after_whitespace() {
+        let buffer = TextBuffer::new("one two three\nfour");
+        let rows: Vec<&str> = buffer
+            .wrap(8)
+            .iter()
+            .map(|row| &buffer.text()[row.start..row.end])
+            .collect();
+        assert_eq!(rows, vec!["one two ", "three", "four"]);
+
+        // wide characters take two columns
+        let buffer = TextBuffer::new("漢字漢字");
+        assert_eq!(buffer.wrap(5).len(), 2);
+    }
+
+    #[test]
+    fn test_undo_groups_words() {
+        let mut buffer = TextBuffer::default();
+        let mut undo = UndoStack::default();
+        for c in "hello world".chars() {
+            let kind = match c {
+                ' ' => EditKind::Whitespace,
+                _ => EditKind::Typing,
+            };
+            undo.edit(&mut buffer, kind, |b| b.insert_str(&c.to_string()));
+        }
+        undo.edit(&mut buffer, EditKind::Deleting, |b| b.backspace());
+        undo.edit(&mut buffer, EditKind::Deleting, |b| b.backspace());
+        assert_eq!(buffer.text(), "hello wor");
+
+        undo.undo(&mut buffer);
+        assert_eq!(buffer.text(), "hello world");
+        undo.undo(&mut buffer);
+        assert_eq!(buffer.text(), "hello ");
+        undo.undo(&mut buffer);
+        assert_eq!(buffer.text(), "");
+
+        undo.redo(&mut buffer);
+        assert_eq!(buffer.text(), "hello ");
+        assert_eq!(buffer.cursor(), 6);
+    }
+}
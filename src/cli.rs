@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::config::Overrides;
+
+#[derive(Parser)]
+#[command(
+    name = "lope",
+    version,
+    about = "A terminal companion for asking questions about your code"
+)]
+pub struct Cli {
+    /// Project directories to browse, each shown as a top-level node
+    #[arg(value_name = "ROOT", default_value = ".")]
+    pub roots: Vec<PathBuf>,
+
+    /// Model to use, overrides the config files
+    #[arg(long, global = true)]
+    pub model: Option<String>,
+
+    /// File containing the api token, overrides the config files
+    #[arg(long, global = true, value_name = "PATH")]
+    pub token_path: Option<String>,
+
+    /// Persona from the config files to use as the system prompt
+    #[arg(long, global = true, value_name = "NAME")]
+    pub persona: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    pub fn config_overrides(&self) -> Overrides {
+        Overrides {
+            model: self.model.clone(),
+            token_path: self.token_path.clone(),
+            persona: self.persona.clone(),
+        }
+    }
+
+    // the roots of the session, the project config is looked up from the first one
+    pub fn project_roots(&self) -> &[PathBuf] {
+        match &self.command {
+            Some(Command::Ask(args)) => &args.roots,
+            None => &self.roots,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Send a single query without starting the interactive ui and print the answer
+    Ask(AskArgs),
+}
+
+#[derive(Args)]
+pub struct AskArgs {
+    /// Project directory the file tree is built from, may be given multiple times
+    #[arg(short, long = "root", value_name = "DIR", default_value = ".")]
+    pub roots: Vec<PathBuf>,
+
+    /// File to include in the query, may be given multiple times
+    #[arg(short, long = "file", value_name = "PATH")]
+    pub files: Vec<PathBuf>,
+
+    /// The prompt to send, read from stdin when omitted
+    #[arg(short, long)]
+    pub prompt: Option<String>,
+
+    /// Print the answer as json along with usage metadata
+    #[arg(long)]
+    pub json: bool,
+}
@@ -0,0 +1,382 @@
+use std::{
+    fs,
+    io::{self, Read},
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::Instant,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use crossbeam::channel::{bounded, Receiver, TryRecvError};
+use globset::Glob;
+
+use crate::{
+    app::App,
+    fuzzy, mentions,
+    openai::{self, ContextSection},
+    widgets::{completion::Candidate, file_browser},
+};
+
+const DEFAULT_EXPORT_PATH: &str = "lope-query.md";
+
+// A command typed at the start of the prompt instead of a question for the model
+#[derive(Debug, PartialEq)]
+pub enum SlashCommand {
+    Model(Option<String>),
+    Clear,
+    Include(String),
+    Run(String),
+    System(Option<String>),
+    Instructions,
+    Export(Option<String>),
+    Help,
+}
+
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "model",
+        usage: "/model [name]",
+        description: "show or switch the model for this session",
+    },
+    CommandInfo {
+        name: "clear",
+        usage: "/clear",
+        description: "clear the prompt, included files and context",
+    },
+    CommandInfo {
+        name: "include",
+        usage: "/include <glob>[:lines]",
+        description: "include the files matching a glob, or some of their lines",
+    },
+    CommandInfo {
+        name: "run",
+        usage: "/run <command>",
+        description: "run a shell command and add its output to the context",
+    },
+    CommandInfo {
+        name: "system",
+        usage: "/system [persona|prompt]",
+        description:
+            "switch persona or replace the system prompt, no argument restores the default",
+    },
+    CommandInfo {
+        name: "instructions",
+        usage: "/instructions",
+        description: "turn the project instructions on or off",
+    },
+    CommandInfo {
+        name: "export",
+        usage: "/export [path]",
+        description: "write the files and context that would be sent to a file",
+    },
+    CommandInfo {
+        name: "help",
+        usage: "/help",
+        description: "list the commands",
+    },
+];
+
+// A `/run` command going on in the background, its output is added to the context once
+// it's done so the ui doesn't wait for it
+pub struct RunningCommand {
+    pub command: String,
+    pub started: Instant,
+    done: Receiver<Result<(ContextSection, ExitStatus)>>,
+}
+
+impl RunningCommand {
+    // the output and exit status once the command has finished
+    pub fn finished(&self) -> Option<Result<(ContextSection, ExitStatus)>> {
+        match self.done.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(anyhow!("`{}` stopped unexpectedly", self.command)))
+            }
+        }
+    }
+}
+
+// None if the text isn't a command, commands are a single line starting with `/`. A prompt
+// led by a path such as `/etc/hosts` isn't a command either
+pub fn parse(text: &str) -> Option<Result<SlashCommand>> {
+    let text = text.trim();
+    let line = text.strip_prefix('/')?;
+    if line.contains('\n') {
+        return None;
+    }
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, Some(arg.trim().to_owned())),
+        None => (line, None),
+    };
+    if name.contains('/') && !COMMANDS.iter().any(|command| command.name == name) {
+        return None;
+    }
+    let required = |arg: Option<String>| match arg {
+        Some(arg) => Ok(arg),
+        None => Err(anyhow::anyhow!("Usage: {}", usage(name))),
+    };
+    Some(match name {
+        "model" => Ok(SlashCommand::Model(arg)),
+        "clear" => Ok(SlashCommand::Clear),
+        "include" => required(arg).map(SlashCommand::Include),
+        "run" => required(arg).map(SlashCommand::Run),
+        "system" => Ok(SlashCommand::System(arg)),
+        "instructions" => Ok(SlashCommand::Instructions),
+        "export" => Ok(SlashCommand::Export(arg)),
+        "help" => Ok(SlashCommand::Help),
+        _ => Err(anyhow::anyhow!(
+            "Unknown command /{}, type /help to list the commands",
+            name
+        )),
+    })
+}
+
+fn usage(name: &str) -> &'static str {
+    COMMANDS
+        .iter()
+        .find(|command| command.name == name)
+        .map_or("", |command| command.usage)
+}
+
+// commands matching the partially typed name, each shown with its usage and description
+pub fn candidates(typed: &str) -> Vec<Candidate> {
+    let width = COMMANDS
+        .iter()
+        .map(|command| command.usage.len())
+        .max()
+        .unwrap_or(0);
+    let mut matches: Vec<(i64, Candidate)> = COMMANDS
+        .iter()
+        .filter_map(|command| {
+            let found = fuzzy::fuzzy_match(typed, command.name)?;
+            let takes_argument = command.usage.contains(' ');
+            let candidate = Candidate {
+                label: format!("{:<width$}  {}", command.usage, command.description),
+                replacement: format!("/{}{}", command.name, if takes_argument { " " } else { "" }),
+                // skip the leading `/` of the usage
+                positions: found.positions.iter().map(|idx| idx + 1).collect(),
+            };
+            Some((found.score, candidate))
+        })
+        .collect();
+    // a stable sort keeps the listed order for equal scores
+    matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+// run the command, returning the message to show in the status bar
+pub fn execute(app: &mut App, command: SlashCommand) -> Result<String> {
+    match command {
+        SlashCommand::Model(None) => Ok(format!("Using {}", app.config.api.model)),
+        SlashCommand::Model(Some(model)) => {
+            app.config.api.model = model;
+            Ok(format!("Switched to {}", app.config.api.model))
+        }
+        SlashCommand::Clear => {
+            for entry in &mut app.file_browser_state.file_list {
+                entry.excluded = true;
+                entry.line_ranges.clear();
+            }
+            app.file_browser_state.update_counts();
+            app.extra_context.clear();
+            Ok("Cleared the prompt, included files and context".to_owned())
+        }
+        SlashCommand::Include(arg) => {
+            // e.g. `src/app.rs:10-20,42` for only those lines
+            let (pattern, ranges) = mentions::split_line_ranges(&arg);
+            let matcher = Glob::new(pattern)
+                .context(format!("Invalid glob {}", pattern))?
+                .compile_matcher();
+            let browser = &mut app.file_browser_state;
+            browser.finish_scan();
+            let matching: Vec<usize> = (0..browser.file_list.len())
+                .filter(|&idx| {
+                    let entry = &browser.file_list[idx];
+                    entry.is_file()
+                        && (matcher.is_match(browser.mention_path(entry))
+                            || matcher.is_match(&entry.display_path))
+                })
+                .collect();
+            if matching.is_empty() {
+                bail!("No files match {}", pattern);
+            }
+            for &idx in &matching {
+                let entry = &mut browser.file_list[idx];
+                if entry.excluded || ranges.is_empty() {
+                    entry.line_ranges.clear();
+                }
+                entry.excluded = false;
+                entry.line_ranges.extend(ranges.iter().cloned());
+                file_browser::merge_ranges(&mut entry.line_ranges);
+            }
+            browser.update_counts();
+            Ok(format!(
+                "Included {} files matching {}",
+                matching.len(),
+                arg
+            ))
+        }
+        SlashCommand::Run(command) => {
+            if let Some(running) = &app.running {
+                bail!("`{}` is still running", running.command);
+            }
+            app.running = Some(run(&command, app)?);
+            Ok(format!("Running `{}`", command))
+        }
+        SlashCommand::System(None) => {
+            app.select_persona(None)?;
+            Ok("Restored the default system prompt".to_owned())
+        }
+        SlashCommand::System(Some(name)) if app.config.personas.contains_key(&name) => {
+            app.select_persona(Some(name))?;
+            Ok(format!(
+                "Using the {} persona for this session",
+                app.persona.as_deref().unwrap_or_default()
+            ))
+        }
+        SlashCommand::System(Some(prompt)) => {
+            app.system_editor_state.set_text(&prompt);
+            app.persona = None;
+            Ok("Replaced the system prompt for this session".to_owned())
+        }
+        SlashCommand::Instructions => Ok(app.toggle_instructions()),
+        SlashCommand::Export(path) => {
+            let path = path.unwrap_or_else(|| DEFAULT_EXPORT_PATH.to_owned());
+            // the prompt is this command, so only the context is exported
+            app.file_browser_state.finish_scan();
+            let query = openai::build_query_with_prompt(app, "");
+            fs::write(&path, &query.text).context(format!("Failed to write {}", path))?;
+            Ok(match query.report() {
+                Some(report) => format!("Wrote the query to {}. {}", path, report),
+                None => format!("Wrote the query to {}", path),
+            })
+        }
+        SlashCommand::Help => Ok(format!(
+            "Commands: {}, type / to see what they do",
+            COMMANDS
+                .iter()
+                .map(|command| command.usage)
+                .collect::<Vec<_>>()
+                .join("  ")
+        )),
+    }
+}
+
+// start a shell command in the first root, stdout and stderr go to one pipe so they stay
+// in order
+fn run(command: &str, app: &App) -> Result<RunningCommand> {
+    let (reader, writer) = io::pipe().context("Failed to create a pipe")?;
+    let mut process = Command::new("sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(writer.try_clone().context("Failed to create a pipe")?)
+        .stderr(writer);
+    if let Some(root) = app.roots.first() {
+        process.current_dir(root);
+    }
+    let mut child = process
+        .spawn()
+        .context(format!("Failed to run `{}`", command))?;
+    // the pipe only ends once no writer is left open on this side
+    drop(process);
+
+    let max_len = app.config.prompt.max_sent_bytes;
+    let (sender, done) = bounded(1);
+    let title = command.to_owned();
+    thread::spawn(move || {
+        let output = read_output(reader, max_len);
+        let result = child
+            .wait()
+            .map(|status| {
+                let body = format!("$ {}\n{}\n[{}]", title, output, status);
+                (
+                    ContextSection::new(&format!("Command `{}`", title), body),
+                    status,
+                )
+            })
+            .context(format!("Failed to run `{}`", title));
+        let _ = sender.send(result);
+    });
+    Ok(RunningCommand {
+        command: command.to_owned(),
+        started: Instant::now(),
+        done,
+    })
+}
+
+// everything read as text, once it's longer than `max_len` only the start and end are kept
+// so a noisy command can't fill the memory or the query
+fn read_output(mut reader: impl Read, max_len: usize) -> String {
+    let half = max_len / 2;
+    let mut kept = Vec::new();
+    let mut left_out = 0;
+    let mut chunk = [0; 8192];
+    loop {
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        kept.extend_from_slice(&chunk[..len]);
+        if kept.len() > max_len {
+            let excess = kept.len() - max_len;
+            kept.drain(half..half + excess);
+            left_out += excess;
+        }
+    }
+    if left_out == 0 {
+        return String::from_utf8_lossy(&kept).into_owned();
+    }
+    format!(
+        "{}\n[... {} bytes of output truncated ...]\n{}",
+        String::from_utf8_lossy(&kept[..half]),
+        left_out,
+        String::from_utf8_lossy(&kept[half..])
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert!(parse("explain this").is_none());
+        assert!(parse("/model\nand more").is_none());
+        assert_eq!(
+            parse(" /model gpt-4o ").unwrap().unwrap(),
+            SlashCommand::Model(Some("gpt-4o".to_owned()))
+        );
+        assert_eq!(
+            parse("/run cargo test -p lope").unwrap().unwrap(),
+            SlashCommand::Run("cargo test -p lope".to_owned())
+        );
+        assert_eq!(parse("/clear").unwrap().unwrap(), SlashCommand::Clear);
+        assert!(parse("/include").unwrap().is_err());
+        assert!(parse("/frobnicate").unwrap().is_err());
+        assert!(parse("/etc/nginx/nginx.conf is failing, why?").is_none());
+    }
+
+    #[test]
+    fn test_long_output_is_truncated() {
+        let output = "a".repeat(10) + &"b".repeat(100) + &"c".repeat(10);
+        assert_eq!(
+            read_output(output.as_bytes(), 20),
+            "aaaaaaaaaa\n[... 100 bytes of output truncated ...]\ncccccccccc"
+        );
+        assert_eq!(read_output("short".as_bytes(), 20), "short");
+    }
+}
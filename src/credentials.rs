@@ -0,0 +1,152 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::{self, ApiConfig};
+
+const PROVIDER: &str = "openai";
+const LEGACY_TOKEN_PATH: &str = ".sgpt/token";
+
+// Resolve the api token, trying in order: the environment variable, the token
+// command, the per-provider token file and the legacy sgpt token file
+pub fn resolve_token(api: &ApiConfig) -> Result<String> {
+    let mut looked = Vec::new();
+
+    match env::var(&api.token_env) {
+        Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_owned()),
+        Ok(_) => looked.push(format!("environment variable {} (empty)", api.token_env)),
+        Err(_) => looked.push(format!("environment variable {} (not set)", api.token_env)),
+    }
+
+    match &api.token_command {
+        Some(command) => return run_token_command(command),
+        None => looked.push("token_command (not configured)".to_owned()),
+    }
+
+    let mut token_files = Vec::new();
+    match &api.token_path {
+        Some(path) => token_files.push(config::expand_home(path)),
+        None => match provider_token_path() {
+            Some(path) => token_files.push(path),
+            None => looked.push(format!(
+                "{} token file (neither XDG_CONFIG_HOME nor HOME is set)",
+                PROVIDER
+            )),
+        },
+    }
+    match env::var_os("HOME") {
+        Some(home) => token_files.push(Path::new(&home).join(LEGACY_TOKEN_PATH)),
+        None => looked.push(format!("~/{} (HOME is not set)", LEGACY_TOKEN_PATH)),
+    }
+
+    for path in token_files {
+        match read_token_file(&path)? {
+            Some(token) if !token.is_empty() => return Ok(token),
+            Some(_) => looked.push(format!("{} (empty)", path.display())),
+            None => looked.push(format!("{} (not found)", path.display())),
+        }
+    }
+
+    bail!(
+        "No API token found, lope looked in:\n  - {}",
+        looked.join("\n  - ")
+    )
+}
+
+// $XDG_CONFIG_HOME/lope/tokens/openai, falling back to ~/.config
+fn provider_token_path() -> Option<PathBuf> {
+    Some(config::user_config_dir()?.join("tokens").join(PROVIDER))
+}
+
+// run the configured command through the shell, its stdout is the token
+fn run_token_command(command: &str) -> Result<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .context(format!("Failed to run token_command `{}`", command))?;
+    if !output.status.success() {
+        bail!(
+            "token_command `{}` failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let token = String::from_utf8(output.stdout)
+        .context(format!("token_command `{}` printed invalid utf-8", command))?;
+    let token = token.trim();
+    if token.is_empty() {
+        bail!("token_command `{}` printed nothing", command);
+    }
+    Ok(token.to_owned())
+}
+
+// read a token file, None if it doesn't exist
+fn read_token_file(path: &Path) -> Result<Option<String>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    check_permissions(path)?;
+
+    let token = fs::read_to_string(path).context(format!(
+        "Failed to read token from {}",
+        path.to_string_lossy()
+    ))?;
+    Ok(Some(token.trim().to_owned()))
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode();
+    if mode & 0o044 != 0 {
+        bail!(
+            "Refusing to use {} because it is readable by other users, run `chmod 600 {}`",
+            path.display(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_token_file_checks_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "sk-test\n").unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(read_token_file(&path).is_err());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(read_token_file(&path).unwrap().as_deref(), Some("sk-test"));
+
+        // a blank file reads as empty, resolve_token reports it instead of sending it
+        fs::write(&path, "  \n").unwrap();
+        assert_eq!(read_token_file(&path).unwrap().as_deref(), Some(""));
+
+        fs::remove_file(&path).unwrap();
+        assert!(read_token_file(&path).unwrap().is_none());
+    }
+}
@@ -1,44 +1,99 @@
 use crate::{
     app::{App, Widget},
-    theme,
-    widgets::options::AppOption,
+    buffer::{display_width, TAB_WIDTH},
+    widgets::{file_browser::Selection, options::AppOption, preview},
 };
 
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span, Text},
-    widgets::{Block, BorderType, Borders, List, ListItem, Paragraph},
+    widgets::{Block, BorderType, Borders, Clear, List, ListItem, Paragraph},
 };
 
 // Draw the file tree with indentation
 pub fn draw_file_tree(f: &mut ratatui::Frame, app: &mut App, area: Rect) {
-    let mut block = Block::default().borders(Borders::ALL).title("File Browser");
+    let browser = &app.file_browser_state;
+    let mut title = match browser.scanning() {
+        true => format!("File Browser (scanning, {} found)", browser.scanned),
+        false => "File Browser".to_owned(),
+    };
+    let shown: Vec<&str> = [
+        (browser.filters().hidden, "hidden"),
+        (browser.filters().ignored, "ignored"),
+    ]
+    .into_iter()
+    .filter_map(|(shown, name)| shown.then_some(name))
+    .collect();
+    if !shown.is_empty() {
+        title.push_str(&format!(" [+{}]", shown.join(", ")));
+    }
+    let mut block = Block::default().borders(Borders::ALL).title(title);
 
     if app.selected_widget == Widget::FileBrowser {
         block = block.border_type(BorderType::Thick)
     }
 
-    let visible_idxs = app.file_browser_state.visible_entries();
+    if let Some(search) = &browser.search {
+        let cursor = if search.typing { "_" } else { "" };
+        block = block.title_bottom(format!(
+            "/{}{} ({} matches)",
+            search.query,
+            cursor,
+            browser.search_matches().len()
+        ));
+    }
+
+    let visible_idxs = browser.visible_entries();
     let items: Vec<ListItem> = visible_idxs
         .iter()
         .map(|&idx| {
-            let entry = &app.file_browser_state.file_list[idx];
+            let entry = &browser.file_list[idx];
             let indentation = " ".repeat(entry.depth * 2);
-            let filename = entry.path.split('/').last().unwrap_or("UNKNOWN");
-            let checked_or_not = if entry.excluded { "" } else { "* " };
-            ListItem::new(format!("{}{}{}", indentation, checked_or_not, filename))
+            let filename = entry.name();
+            let checked_or_not = match browser.selection(idx) {
+                Selection::All => "* ",
+                Selection::Partial => "~ ",
+                Selection::None => "",
+            };
+            let mut spans = vec![Span::raw(format!("{}{}", indentation, checked_or_not))];
+            match browser
+                .search
+                .as_ref()
+                .and_then(|search| search.find(filename))
+            {
+                Some(positions) => spans.extend(highlight_matches(filename, &positions)),
+                None => spans.push(Span::raw(filename.to_owned())),
+            }
+            if !entry.excluded && !entry.line_ranges.is_empty() {
+                let ranges: Vec<String> = entry
+                    .line_ranges
+                    .iter()
+                    .map(|range| format!("{}-{}", range.start(), range.end()))
+                    .collect();
+                spans.push(Span::styled(
+                    format!(" :{}", ranges.join(",")),
+                    Style::default().add_modifier(Modifier::DIM),
+                ));
+            }
+            if entry.deleted {
+                spans.push(Span::styled(
+                    " (deleted)",
+                    Style::default().add_modifier(Modifier::ITALIC | Modifier::DIM),
+                ));
+            }
+            ListItem::new(Line::from(spans))
         })
         .collect();
 
     // 2. Build the `List` widget
     let list = List::new(items)
         .block(block)
-        .style(Style::default().bg(theme::GRAY))
+        .style(Style::default().bg(app.config.theme.background))
         .highlight_style(
             Style::default()
-                .fg(theme::YELLOW) // yellow
-                .bg(theme::LIGHT_GREY)
+                .fg(app.config.theme.selected)
+                .bg(app.config.theme.highlight)
                 .add_modifier(Modifier::BOLD),
         )
         .highlight_symbol("> ");
@@ -47,25 +102,215 @@ pub fn draw_file_tree(f: &mut ratatui::Frame, app: &mut App, area: Rect) {
     f.render_stateful_widget(list, area, &mut app.file_browser_state.list_state);
 }
 
-// Draw the prompt editor
-pub fn draw_prompt_editor(f: &mut ratatui::Frame, app: &App, area: Rect) {
-    let mut block = Block::default()
-        .borders(Borders::ALL)
-        .title("Prompt Editor");
+// Draw the file selected in the tree, with line numbers that show which lines are sent
+pub fn draw_preview(f: &mut ratatui::Frame, app: &mut App, area: Rect) {
+    let state = &mut app.preview_state;
+    state.show(app.file_browser_state.selected_entry());
+    // the lines of the file that are sent, or whether all of them are
+    let mut included: &[std::ops::RangeInclusive<usize>] = &[];
+    let mut all_sent = false;
+    let mut title = "Preview".to_owned();
+    if let Some(entry) = app.file_browser_state.selected_entry() {
+        if entry.is_file() {
+            title = format!("Preview: {}", entry.display_path);
+            if !entry.excluded {
+                included = &entry.line_ranges;
+                all_sent = entry.line_ranges.is_empty();
+            }
+        }
+    }
+    if state.anchor.is_some() {
+        title.push_str(" [selecting lines]");
+    }
+    let mut block = Block::default().borders(Borders::ALL).title(title);
+    if app.selected_widget == Widget::Preview {
+        block = block.border_type(BorderType::Thick);
+    }
+    let inner = block.inner(area);
+    state.update_view(inner.height as usize);
+
+    let text = match &state.content {
+        preview::Content::Note(note) => Text::from(Span::styled(
+            note.clone(),
+            Style::default().add_modifier(Modifier::ITALIC),
+        )),
+        preview::Content::Lines(lines) => {
+            // line numbers are as wide as the last one
+            let width = lines.len().to_string().len();
+            let selected = state
+                .anchor
+                .map(|anchor| anchor.min(state.cursor)..=anchor.max(state.cursor));
+            lines
+                .iter()
+                .enumerate()
+                .skip(state.scroll)
+                .take(inner.height as usize)
+                .map(|(number, line)| {
+                    let sent =
+                        all_sent || included.iter().any(|range| range.contains(&(number + 1)));
+                    let gutter_style = match sent {
+                        true => Style::default().fg(app.config.theme.selected),
+                        false => Style::default().add_modifier(Modifier::DIM),
+                    };
+                    let gutter = Span::styled(format!("{:>width$} ", number + 1), gutter_style);
+                    let mut line = line.clone();
+                    line.spans.insert(0, gutter);
+                    if selected
+                        .as_ref()
+                        .is_some_and(|range| range.contains(&number))
+                    {
+                        line = line.style(Style::default().add_modifier(Modifier::REVERSED));
+                    } else if number == state.cursor && app.selected_widget == Widget::Preview {
+                        line = line.style(Style::default().bg(app.config.theme.highlight));
+                    }
+                    line
+                })
+                .collect::<Vec<_>>()
+                .into()
+        }
+    };
+    let paragraph = Paragraph::new(text).block(block).style(
+        Style::default()
+            .fg(app.config.theme.text)
+            .bg(app.config.theme.background),
+    );
+    f.render_widget(paragraph, area);
+}
+
+// Draw the prompt editor, or the system prompt editor while it's being edited
+pub fn draw_prompt_editor(f: &mut ratatui::Frame, app: &mut App, area: Rect) {
+    let mut title = match (app.editing_system_prompt, &app.persona) {
+        (true, Some(persona)) => format!("System Prompt ({})", persona),
+        (true, None) => "System Prompt".to_owned(),
+        (false, Some(persona)) => format!("Prompt Editor ({})", persona),
+        (false, None) => "Prompt Editor".to_owned(),
+    };
+    if let Some(mode) = app.active_editor().mode() {
+        title.push_str(&format!(" [{}]", mode));
+    }
+    let mut block = Block::default().borders(Borders::ALL).title(title);
     if app.selected_widget == Widget::PromptEditor {
         block = block.border_type(BorderType::Thick);
     }
-    let paragraph = Paragraph::new(app.prompt_editor_state.get_display_text())
-        .block(block)
-        .style(Style::default().fg(theme::LIGHT_GREEN).bg(theme::GRAY));
+
+    let inner = block.inner(area);
+    let state = app.active_editor();
+    state.update_view(inner.width as usize, inner.height as usize);
+
+    let buffer = state.buffer();
+    let rows = buffer.wrap(inner.width as usize);
+    let selection = state.selection();
+    let lines: Vec<Line> = rows
+        .iter()
+        .skip(state.scroll())
+        .take(inner.height as usize)
+        .map(|row| {
+            // split the row around the selection so it can be highlighted
+            let mut bounds = vec![row.start, row.end];
+            if let Some(selection) = &selection {
+                bounds.push(selection.start.clamp(row.start, row.end));
+                bounds.push(selection.end.clamp(row.start, row.end));
+            }
+            bounds.sort();
+            let spans: Vec<Span> = bounds
+                .windows(2)
+                .filter(|pair| pair[0] < pair[1])
+                .map(|pair| {
+                    let text =
+                        buffer.text()[pair[0]..pair[1]].replace('\t', &" ".repeat(TAB_WIDTH));
+                    match &selection {
+                        Some(selection) if selection.contains(&pair[0]) => {
+                            Span::styled(text, Style::default().add_modifier(Modifier::REVERSED))
+                        }
+                        _ => Span::raw(text),
+                    }
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect();
+
+    let (row, column) = buffer.cursor_row_column(&rows);
+    let column = column.min(inner.width.saturating_sub(1) as usize) as u16;
+    let cursor = (inner.x + column, inner.y + (row - state.scroll()) as u16);
+
+    let paragraph = Paragraph::new(lines).block(block).style(
+        Style::default()
+            .fg(app.config.theme.text)
+            .bg(app.config.theme.background),
+    );
     f.render_widget(paragraph, area);
+
+    if app.selected_widget == Widget::PromptEditor {
+        f.set_cursor_position(cursor);
+        draw_completion(f, app, inner, cursor);
+    }
+}
+
+// Draw the completion popup below the cursor, or above it when there is no room
+fn draw_completion(f: &mut ratatui::Frame, app: &mut App, area: Rect, cursor: (u16, u16)) {
+    let theme = &app.config.theme;
+    let Some(completion) = &mut app.completion else {
+        return;
+    };
+
+    let items: Vec<ListItem> = completion
+        .candidates
+        .iter()
+        .map(|candidate| {
+            ListItem::new(Line::from(highlight_matches(
+                &candidate.label,
+                &candidate.positions,
+            )))
+        })
+        .collect();
+
+    let longest = completion
+        .candidates
+        .iter()
+        .map(|candidate| display_width(&candidate.label))
+        .max()
+        .unwrap_or(0);
+    let width = (longest as u16 + 4).min(area.width);
+    let height = (items.len() as u16 + 2).min(area.height);
+    let x = cursor.0.min(area.right().saturating_sub(width));
+    let below = area.bottom().saturating_sub(cursor.1 + 1);
+    let y = match below >= height {
+        true => cursor.1 + 1,
+        false => cursor.1.saturating_sub(height).max(area.y),
+    };
+    let popup = Rect::new(x, y, width, height);
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL))
+        .style(Style::default().fg(theme.text).bg(theme.background))
+        .highlight_style(Style::default().fg(theme.selected).bg(theme.highlight));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut completion.list_state);
 }
 
 pub fn draw_bottom_options(f: &mut ratatui::Frame, app: &App, area: Rect) {
     let mut block = Block::default()
         .borders(Borders::ALL)
         .title("Options")
-        .style(Style::default().bg(theme::GRAY));
+        .style(Style::default().bg(app.config.theme.background));
+    if !app.instructions.is_empty() {
+        let indicator = match app.instructions_enabled {
+            true => format!("Instructions: {}", app.instructions.len()),
+            false => "Instructions: off".to_owned(),
+        };
+        block = block.title(indicator);
+    }
+    if let Some(running) = &app.running {
+        block = block.title(format!(
+            "Running `{}` {}s",
+            running.command,
+            running.started.elapsed().as_secs()
+        ));
+    }
+    if let Some(status) = &app.status {
+        block = block.title(Line::from(status.as_str()).right_aligned());
+    }
 
     if app.selected_widget == Widget::Options {
         block = block.border_type(BorderType::Thick)
@@ -76,7 +321,7 @@ pub fn draw_bottom_options(f: &mut ratatui::Frame, app: &App, area: Rect) {
             Span::styled(
                 "[Send]",
                 Style::default()
-                    .fg(theme::BLUE)
+                    .fg(app.config.theme.send)
                     .add_modifier(Modifier::REVERSED),
             )
         } else {
@@ -87,7 +332,7 @@ pub fn draw_bottom_options(f: &mut ratatui::Frame, app: &App, area: Rect) {
             Span::styled(
                 "[Quit]",
                 Style::default()
-                    .fg(theme::PURPLE)
+                    .fg(app.config.theme.quit)
                     .add_modifier(Modifier::REVERSED),
             )
         } else {
@@ -105,6 +350,161 @@ pub fn draw_bottom_options(f: &mut ratatui::Frame, app: &App, area: Rect) {
     f.render_widget(paragraph, area);
 }
 
+// Draw the template picker as a popup over `area`
+pub fn draw_template_picker(f: &mut ratatui::Frame, app: &mut App, area: Rect) {
+    let theme = &app.config.theme;
+    let Some(picker) = &mut app.template_picker else {
+        return;
+    };
+    let style = Style::default().fg(theme.text).bg(theme.background);
+
+    if let Some((question, answer)) = picker.question() {
+        let popup = centered(area, 60, 3);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Thick)
+            .title(question.to_owned());
+        let inner = block.inner(popup);
+        let before = &answer.text()[..answer.cursor()];
+        let column = display_width(before).min(inner.width.saturating_sub(1) as usize) as u16;
+        f.render_widget(Clear, popup);
+        f.render_widget(
+            Paragraph::new(answer.text().to_owned())
+                .block(block)
+                .style(style),
+            popup,
+        );
+        f.set_cursor_position((inner.x + column, inner.y));
+        return;
+    }
+
+    let items: Vec<ListItem> = picker
+        .templates()
+        .iter()
+        .map(|template| ListItem::new(template.name.clone()))
+        .collect();
+    let popup = centered(area, 40, items.len() as u16 + 2);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Thick)
+                .title("Templates"),
+        )
+        .style(style)
+        .highlight_style(
+            Style::default()
+                .fg(theme.selected)
+                .bg(theme.highlight)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol("> ");
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut picker.list_state);
+}
+
+// Draw the history search as a popup over `area`, newest matches first
+pub fn draw_history_search(f: &mut ratatui::Frame, app: &mut App, area: Rect) {
+    let theme = &app.config.theme;
+    let Some(search) = &mut app.history_search else {
+        return;
+    };
+
+    // multi-line prompts are shown on one line
+    let items: Vec<ListItem> = search
+        .matches()
+        .map(|prompt| ListItem::new(prompt.split_whitespace().collect::<Vec<_>>().join(" ")))
+        .collect();
+    let popup = centered(area, 80, 12);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Thick)
+                .title(format!("History search: {}", search.query)),
+        )
+        .style(Style::default().fg(theme.text).bg(theme.background))
+        .highlight_style(
+            Style::default()
+                .fg(theme.selected)
+                .bg(theme.highlight)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol("> ");
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut search.list_state);
+    let column = popup.x
+        + 1
+        + display_width("History search: ") as u16
+        + display_width(&search.query) as u16;
+    f.set_cursor_position((column.min(popup.right().saturating_sub(2)), popup.y));
+}
+
+// Draw the file finder as a popup over `area`, with the matched characters highlighted
+pub fn draw_file_finder(f: &mut ratatui::Frame, app: &mut App, area: Rect) {
+    let theme = &app.config.theme;
+    let Some(finder) = &mut app.file_finder else {
+        return;
+    };
+    let browser = &app.file_browser_state;
+
+    let items: Vec<ListItem> = finder
+        .results
+        .iter()
+        .map(|(idx, positions)| {
+            let entry = &browser.file_list[*idx];
+            let marker = if entry.excluded { "  " } else { "* " };
+            let mut spans = vec![Span::raw(marker)];
+            spans.extend(highlight_matches(browser.mention_path(entry), positions));
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+    let popup = centered(area, 70, area.height * 3 / 5);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Thick)
+                .title(format!("Find file: {}", finder.query))
+                .title_bottom(" Enter: jump  Tab: include  Esc: close "),
+        )
+        .style(Style::default().fg(theme.text).bg(theme.background))
+        .highlight_style(Style::default().fg(theme.selected).bg(theme.highlight))
+        .highlight_symbol("> ");
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut finder.list_state);
+    let column =
+        popup.x + 1 + display_width("Find file: ") as u16 + display_width(&finder.query) as u16;
+    f.set_cursor_position((column.min(popup.right().saturating_sub(2)), popup.y));
+}
+
+// the label split into spans, with the chars at `positions` emphasised
+fn highlight_matches(label: &str, positions: &[usize]) -> Vec<Span<'static>> {
+    label
+        .chars()
+        .enumerate()
+        .map(|(idx, c)| match positions.contains(&idx) {
+            true => Span::styled(
+                c.to_string(),
+                Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            ),
+            false => Span::raw(c.to_string()),
+        })
+        .collect()
+}
+
+// a rect `width` percent wide and `height` rows tall in the middle of `area`
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = area.width * width / 100;
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
 pub fn ui(f: &mut ratatui::Frame, app: &mut App) {
     // First, split the screen vertically so we can have a thin pane at the bottom
     let vertical_chunks = Layout::default()
@@ -116,10 +516,29 @@ pub fn ui(f: &mut ratatui::Frame, app: &mut App) {
     // Now, split the top pane horizontally for the file tree and prompt editor
     let main_chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+        .constraints([
+            Constraint::Percentage(app.config.layout.file_browser_width),
+            Constraint::Percentage(100 - app.config.layout.file_browser_width),
+        ])
         .split(vertical_chunks[0]);
 
     draw_file_tree(f, app, main_chunks[0]);
-    draw_prompt_editor(f, app, main_chunks[1]);
+    if app.config.layout.preview {
+        // the preview sits above the prompt editor
+        let right_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Percentage(app.config.layout.preview_height),
+                Constraint::Percentage(100 - app.config.layout.preview_height),
+            ])
+            .split(main_chunks[1]);
+        draw_preview(f, app, right_chunks[0]);
+        draw_prompt_editor(f, app, right_chunks[1]);
+    } else {
+        draw_prompt_editor(f, app, main_chunks[1]);
+    }
     draw_bottom_options(f, app, vertical_chunks[1]);
+    draw_template_picker(f, app, main_chunks[1]);
+    draw_history_search(f, app, main_chunks[1]);
+    draw_file_finder(f, app, f.area());
 }
@@ -0,0 +1,62 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+use tempfile::Builder;
+
+const TTY_PATH: &str = "/dev/tty";
+
+// Open $VISUAL or $EDITOR on a temporary file holding `text` and return the edited text.
+// The terminal must already have left raw mode and the alternate screen.
+pub fn edit(text: &str) -> Result<String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned());
+
+    // a new file with a random name only we can read, it's removed when dropped
+    let mut file = Builder::new()
+        .prefix("lope-prompt-")
+        .suffix(".md")
+        .tempfile()
+        .context("Failed to create a temporary file for the prompt")?;
+    file.write_all(text.as_bytes())
+        .context(format!("Failed to write {}", file.path().display()))?;
+    let path = file.path().to_owned();
+
+    // run through the shell so editors configured with arguments, e.g. `code --wait`, work
+    // the editor talks to the terminal directly since stdin may be a pipe
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .stdin(tty()?)
+        .stdout(tty()?)
+        .status()
+        .context(format!("Failed to run editor `{}`", editor));
+
+    // editors may replace the file rather than write to it, so it's read again by path
+    let edited = fs::read_to_string(&path);
+    drop(file);
+
+    let status = status?;
+    if !status.success() {
+        bail!("Editor `{}` exited with {}", editor, status);
+    }
+    let edited = edited.context("Failed to read the edited prompt")?;
+    // editors usually end the file with a newline
+    Ok(edited.trim_end_matches('\n').to_owned())
+}
+
+fn tty() -> Result<Stdio> {
+    let tty = File::options()
+        .read(true)
+        .write(true)
+        .open(TTY_PATH)
+        .context(format!("Failed to open {}", TTY_PATH))?;
+    Ok(Stdio::from(tty))
+}
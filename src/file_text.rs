@@ -0,0 +1,192 @@
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+
+// a NUL byte this close to the start means the file isn't text
+const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+// A file decoded to text
+pub struct FileText {
+    pub text: String,
+    // the encoding it was decoded from, None for UTF-8
+    pub encoding: Option<&'static str>,
+}
+
+// The file as text, None if it's binary
+pub fn read(path: &Path) -> Result<Option<FileText>> {
+    let bytes = fs::read(path).context(format!("Failed to read {}", path.display()))?;
+    Ok(decode(&bytes))
+}
+
+// Like read followed by truncate, but only the start and end of a long file are read.
+// The second value says whether anything was left out
+pub fn read_truncated(path: &Path, max_len: usize) -> Result<Option<(String, bool)>> {
+    let context = || format!("Failed to read {}", path.display());
+    let mut file = File::open(path).with_context(context)?;
+    let len = file.metadata().with_context(context)?.len() as usize;
+    // decoding can make text longer or shorter, so files not far over the limit are
+    // decoded whole and truncated after
+    if len <= max_len * 2 {
+        let mut bytes = Vec::with_capacity(len);
+        file.read_to_end(&mut bytes).with_context(context)?;
+        return Ok(
+            decode(&bytes).map(|decoded| match truncate(&decoded.text, max_len) {
+                Cow::Borrowed(_) => (decoded.text, false),
+                Cow::Owned(text) => (text, true),
+            }),
+        );
+    }
+
+    // the halves are even so UTF-16 characters aren't split
+    let half = max_len / 4 * 2;
+    let mut head = vec![0; half];
+    let mut tail = vec![0; half];
+    file.read_exact(&mut head).with_context(context)?;
+    file.seek(SeekFrom::End(-(half as i64)))
+        .with_context(context)?;
+    file.read_exact(&mut tail).with_context(context)?;
+    let Some((encoding, bom_len)) = detect(&head) else {
+        return Ok(None);
+    };
+    let (head, _) = encoding.decode_without_bom_handling(&head[bom_len..]);
+    let (tail, _) = encoding.decode_without_bom_handling(&tail);
+    // the lines cut in two, and any characters with them, are left out
+    let head = head
+        .rfind('\n')
+        .map_or(&*head, |newline| &head[..newline + 1]);
+    let tail = tail
+        .find('\n')
+        .filter(|&newline| newline + 1 < tail.len())
+        .map_or(&*tail, |newline| &tail[newline + 1..]);
+    Ok(Some((
+        format!(
+            "{}\n[... {} bytes truncated ...]\n{}",
+            head.trim_end_matches('\n'),
+            len.saturating_sub(bom_len + head.len() + tail.len()),
+            tail
+        ),
+        true,
+    )))
+}
+
+// Files are taken as UTF-8 unless they start with a byte order mark or aren't valid UTF-8,
+// then the encoding is guessed. Bytes that don't decode are replaced rather than failing
+pub fn decode(bytes: &[u8]) -> Option<FileText> {
+    let (encoding, bom_len) = detect(bytes)?;
+    let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+    Some(FileText {
+        text: text.into_owned(),
+        encoding: (encoding != UTF_8).then_some(encoding.name()),
+    })
+}
+
+// the encoding of the bytes and the length of their byte order mark, None if they're binary
+fn detect(bytes: &[u8]) -> Option<(&'static Encoding, usize)> {
+    // UTF-16 text is full of NUL bytes, so a byte order mark is looked for first
+    if let Some(found) = Encoding::for_bom(bytes) {
+        return Some(found);
+    }
+    if is_binary(bytes) {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return Some((UTF_8, 0)),
+        // the start of a file may end part way through a character
+        Err(err) if err.error_len().is_none() => return Some((UTF_8, 0)),
+        Err(_) => {}
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    Some((detector.guess(None, true), 0))
+}
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+// About `max_len` bytes of the text, the start and end are kept on line boundaries where
+// possible and a marker says how much was left out in between
+pub fn truncate(text: &str, max_len: usize) -> Cow<'_, str> {
+    if text.len() <= max_len {
+        return Cow::Borrowed(text);
+    }
+    let bytes = text.as_bytes();
+    let half = max_len / 2;
+
+    let mut head_end = half;
+    while !text.is_char_boundary(head_end) {
+        head_end -= 1;
+    }
+    if let Some(newline) = bytes[..head_end].iter().rposition(|&b| b == b'\n') {
+        head_end = newline + 1;
+    }
+    let mut tail_start = text.len() - half;
+    while !text.is_char_boundary(tail_start) {
+        tail_start += 1;
+    }
+    if let Some(newline) = bytes[tail_start - 1..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .filter(|&newline| tail_start + newline < text.len())
+    {
+        tail_start += newline;
+    }
+
+    let omitted = &text[head_end..tail_start];
+    Cow::Owned(format!(
+        "{}\n[... {} lines, {} bytes truncated ...]\n{}",
+        text[..head_end].trim_end_matches('\n'),
+        omitted.lines().count(),
+        omitted.len(),
+        &text[tail_start..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_and_truncate() {
+        assert!(decode(b"PNG\0\x01\x02").is_none());
+        // latin-1 text that isn't valid UTF-8
+        let legacy = decode(b"caf\xe9 cr\xe8me").unwrap();
+        assert_eq!(legacy.text, "café crème");
+        assert_eq!(legacy.encoding, Some("windows-1252"));
+        let utf16 = decode(b"\xff\xfeh\0i\0").unwrap();
+        assert_eq!(utf16.text, "hi");
+
+        let text = (1..=100)
+            .map(|n| format!("line {}\n", n))
+            .collect::<String>();
+        let truncated = truncate(&text, 60);
+        assert!(truncated.starts_with("line 1\nline 2\nline 3\nline 4\n[... "));
+        assert!(truncated.ends_with("]\nline 98\nline 99\nline 100\n"));
+        assert_eq!(truncate("short", 60), "short");
+
+        // long files only have their start and end read
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("long.txt");
+        fs::write(&path, &text).unwrap();
+        let (read, truncated) = read_truncated(&path, 30).unwrap().unwrap();
+        assert!(truncated);
+        assert_eq!(
+            read,
+            "line 1\nline 2\n[... 769 bytes truncated ...]\nline 100\n"
+        );
+        fs::write(&path, "short\n").unwrap();
+        assert_eq!(
+            read_truncated(&path, 30).unwrap().unwrap(),
+            ("short\n".to_owned(), false)
+        );
+    }
+}
@@ -0,0 +1,117 @@
+// Fuzzy matching of a typed query against paths, used by the completion popups
+
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 12;
+// matches at the start of a path component or word
+const COMPONENT_START: i64 = 24;
+const WORD_START: i64 = 12;
+const GAP: i64 = 1;
+
+pub struct Match {
+    pub score: i64,
+    // char indices of the matched characters in the candidate
+    pub positions: Vec<usize>,
+}
+
+// Match the query's characters in order anywhere in the candidate, ignoring case.
+// The best scoring alignment is chosen, favouring consecutive characters and
+// matches at the start of path components.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<Match> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let chars: Vec<char> = candidate.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    // cheap check before doing the full alignment
+    let mut rest = lower.iter();
+    if !query.iter().all(|q| rest.any(|c| c == q)) {
+        return None;
+    }
+    if query.is_empty() {
+        return Some(Match {
+            score: 0,
+            positions: Vec::new(),
+        });
+    }
+
+    let bonus: Vec<i64> = (0..chars.len())
+        .map(|j| match j.checked_sub(1).map(|prev| chars[prev]) {
+            None | Some('/') => COMPONENT_START,
+            Some('_' | '-' | '.' | ' ') => WORD_START,
+            Some(prev) if prev.is_lowercase() && chars[j].is_uppercase() => WORD_START,
+            _ => 0,
+        })
+        .collect();
+
+    // best[i][j] is the best score with query[i] matched at candidate[j], from[i][j] where query[i - 1] matched
+    let mut best = vec![vec![None; chars.len()]; query.len()];
+    let mut from = vec![vec![0; chars.len()]; query.len()];
+    for (j, c) in lower.iter().enumerate() {
+        if *c == query[0] {
+            best[0][j] = Some(MATCH + bonus[j]);
+        }
+    }
+    for i in 1..query.len() {
+        // best of best[i - 1][k] + k over k < j - 1, gaps cost GAP per skipped char
+        let mut gapped: Option<(i64, usize)> = None;
+        for j in 1..chars.len() {
+            if j >= 2 {
+                if let Some(score) = best[i - 1][j - 2] {
+                    let value = score + (j - 2) as i64 * GAP;
+                    if gapped.is_none_or(|(best, _)| value > best) {
+                        gapped = Some((value, j - 2));
+                    }
+                }
+            }
+            if lower[j] != query[i] {
+                continue;
+            }
+            let consecutive = best[i - 1][j - 1].map(|score| (score + CONSECUTIVE, j - 1));
+            let gap = gapped.map(|(value, k)| (value - (j - 1) as i64 * GAP, k));
+            let chosen = match (consecutive, gap) {
+                (Some(a), Some(b)) => Some(if a.0 >= b.0 { a } else { b }),
+                (a, b) => a.or(b),
+            };
+            if let Some((score, k)) = chosen {
+                best[i][j] = Some(score + MATCH + bonus[j]);
+                from[i][j] = k;
+            }
+        }
+    }
+
+    let last = query.len() - 1;
+    let (mut j, score) = best[last]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, score)| score.map(|score| (j, score)))
+        .max_by_key(|&(j, score)| (score, std::cmp::Reverse(j)))?;
+    let mut positions = vec![j];
+    for i in (1..query.len()).rev() {
+        j = from[i][j];
+        positions.push(j);
+    }
+    positions.reverse();
+    Some(Match { score, positions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match_prefers_path_components() {
+        let m = fuzzy_match("wpe", "src/widgets/prompt_editor.rs").unwrap();
+        assert_eq!(m.positions, vec![4, 12, 19]);
+        assert!(fuzzy_match("xyz", "src/app.rs").is_none());
+
+        let exact = fuzzy_match("app", "src/app.rs").unwrap();
+        let scattered = fuzzy_match("app", "src/a_p_p.rs").unwrap();
+        assert!(exact.score > scattered.score);
+        assert_eq!(
+            fuzzy_match("APP", "src/app.rs").unwrap().positions,
+            vec![4, 5, 6]
+        );
+    }
+}
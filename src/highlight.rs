@@ -0,0 +1,294 @@
+use std::path::Path;
+
+use ratatui::{
+    style::{Modifier, Style},
+    text::{Line, Span},
+};
+
+use crate::{buffer::TAB_WIDTH, theme::Theme};
+
+// What the highlighter knows about a language, enough for comments, strings and keywords
+// without a full grammar
+struct Syntax {
+    keywords: &'static [&'static str],
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+}
+
+const RUST: Syntax = Syntax {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    // a single quote is more often a lifetime than a char
+    quotes: &['"'],
+};
+
+// C, C++, Java, Go, JavaScript, TypeScript and the like share one table
+const C_LIKE: Syntax = Syntax {
+    keywords: &[
+        "abstract",
+        "async",
+        "await",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "defer",
+        "delete",
+        "do",
+        "else",
+        "enum",
+        "export",
+        "extends",
+        "false",
+        "final",
+        "finally",
+        "for",
+        "func",
+        "function",
+        "go",
+        "if",
+        "implements",
+        "import",
+        "in",
+        "interface",
+        "let",
+        "new",
+        "null",
+        "package",
+        "private",
+        "protected",
+        "public",
+        "return",
+        "static",
+        "struct",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "type",
+        "typedef",
+        "var",
+        "void",
+        "while",
+    ],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+};
+
+const PYTHON: Syntax = Syntax {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+        "elif", "else", "except", "False", "finally", "for", "from", "if", "import", "in", "is",
+        "lambda", "None", "not", "or", "pass", "raise", "return", "True", "try", "while", "with",
+        "yield",
+    ],
+    line_comment: Some("#"),
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const SHELL: Syntax = Syntax {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+        "in", "local", "return", "then", "while",
+    ],
+    line_comment: Some("#"),
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+// toml and yaml
+const CONFIG: Syntax = Syntax {
+    keywords: &["true", "false", "null"],
+    line_comment: Some("#"),
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+fn syntax_for(path: &Path) -> Option<&'static Syntax> {
+    let extension = path.extension()?.to_str()?;
+    match extension {
+        "rs" => Some(&RUST),
+        "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "go" | "java" | "kt" | "swift" | "js" | "jsx"
+        | "mjs" | "ts" | "tsx" => Some(&C_LIKE),
+        "py" => Some(&PYTHON),
+        "sh" | "bash" | "zsh" => Some(&SHELL),
+        "toml" | "yaml" | "yml" => Some(&CONFIG),
+        _ => None,
+    }
+}
+
+// The file's lines with tabs expanded, colored by the syntax of its extension
+pub fn highlight(text: &str, path: &Path, theme: &Theme) -> Vec<Line<'static>> {
+    let tab = " ".repeat(TAB_WIDTH);
+    let lines = text.lines().map(|line| line.replace('\t', &tab));
+    let Some(syntax) = syntax_for(path) else {
+        return lines.map(Line::from).collect();
+    };
+    // block comments go on across lines
+    let mut in_comment = false;
+    lines
+        .map(|line| highlight_line(&line, syntax, theme, &mut in_comment))
+        .collect()
+}
+
+fn highlight_line(
+    line: &str,
+    syntax: &Syntax,
+    theme: &Theme,
+    in_comment: &mut bool,
+) -> Line<'static> {
+    let comment = Style::default()
+        .fg(theme.comment)
+        .add_modifier(Modifier::ITALIC);
+    let mut spans = Vec::new();
+    // start of the text not yet put in a span
+    let mut plain = 0;
+    let mut i = 0;
+    let push = |spans: &mut Vec<Span<'static>>, plain: &mut usize, start, end, style| {
+        if *plain < start {
+            spans.push(Span::raw(line[*plain..start].to_owned()));
+        }
+        spans.push(Span::styled(line[start..end].to_owned(), style));
+        *plain = end;
+    };
+
+    while i < line.len() {
+        let rest = &line[i..];
+        if !*in_comment
+            && syntax
+                .line_comment
+                .is_some_and(|start| rest.starts_with(start))
+        {
+            push(&mut spans, &mut plain, i, line.len(), comment);
+            break;
+        }
+        let opens = syntax
+            .block_comment
+            .is_some_and(|(open, _)| !*in_comment && rest.starts_with(open));
+        if *in_comment || opens {
+            let (open, close) = syntax.block_comment.unwrap_or_default();
+            let skip = if opens { open.len() } else { 0 };
+            let end = match rest[skip..].find(close) {
+                Some(end) => {
+                    *in_comment = false;
+                    i + skip + end + close.len()
+                }
+                None => {
+                    *in_comment = true;
+                    line.len()
+                }
+            };
+            push(&mut spans, &mut plain, i, end, comment);
+            i = end;
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap_or_default();
+        let word_len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if syntax.quotes.contains(&c) {
+            // up to the closing quote, skipping escaped ones, or the end of the line
+            let mut escaped = false;
+            let end = rest[1..]
+                .char_indices()
+                .find(|&(_, next)| {
+                    let closes = next == c && !escaped;
+                    escaped = next == '\\' && !escaped;
+                    closes
+                })
+                .map_or(line.len(), |(offset, _)| i + 1 + offset + 1);
+            push(
+                &mut spans,
+                &mut plain,
+                i,
+                end,
+                Style::default().fg(theme.string),
+            );
+            i = end;
+        } else if c.is_ascii_digit() {
+            push(
+                &mut spans,
+                &mut plain,
+                i,
+                i + word_len,
+                Style::default().fg(theme.number),
+            );
+            i += word_len;
+        } else if word_len > 0 {
+            if syntax.keywords.contains(&&rest[..word_len]) {
+                push(
+                    &mut spans,
+                    &mut plain,
+                    i,
+                    i + word_len,
+                    Style::default().fg(theme.keyword),
+                );
+            }
+            i += word_len;
+        } else {
+            i += c.len_utf8();
+        }
+    }
+    if plain < line.len() {
+        spans.push(Span::raw(line[plain..].to_owned()));
+    }
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::Color;
+
+    use super::*;
+
+    #[test]
+    fn test_highlight_rust() {
+        let theme = Theme::default();
+        let lines = highlight(
+            "let x = \"a \\\" b\"; // done\n/* one\ntwo */ fn",
+            Path::new("main.rs"),
+            &theme,
+        );
+        let spans: Vec<Vec<(&str, Option<Color>)>> = lines
+            .iter()
+            .map(|line| {
+                line.spans
+                    .iter()
+                    .map(|span| (span.content.as_ref(), span.style.fg))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                vec![
+                    ("let", Some(theme.keyword)),
+                    (" x = ", None),
+                    ("\"a \\\" b\"", Some(theme.string)),
+                    ("; ", None),
+                    ("// done", Some(theme.comment)),
+                ],
+                vec![("/* one", Some(theme.comment))],
+                vec![
+                    ("two */", Some(theme.comment)),
+                    (" ", None),
+                    ("fn", Some(theme.keyword))
+                ],
+            ]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::config;
+
+const HISTORY_DIR: &str = "history";
+const HISTORY_LIMIT: usize = 1000;
+
+// Prompts sent from a project, oldest first, stored as one json string per line in
+// $XDG_STATE_HOME/lope/history/ under a name derived from the project root
+#[derive(Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+    // the entry shown in the editor while cycling with Up and Down
+    position: Option<usize>,
+    // what was in the editor before cycling started
+    draft: String,
+}
+
+impl History {
+    pub fn load(root: &Path) -> Self {
+        let path = config::user_state_dir().map(|dir| dir.join(HISTORY_DIR).join(file_name(root)));
+        match path {
+            Some(path) => Self::load_from(path),
+            None => Self::default(),
+        }
+    }
+
+    // a missing or unreadable history starts out empty
+    pub fn load_from(path: PathBuf) -> Self {
+        let mut entries: Vec<String> = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let skip = entries.len().saturating_sub(HISTORY_LIMIT);
+        entries.drain(..skip);
+        Self {
+            path: Some(path),
+            entries,
+            ..Default::default()
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // record a sent prompt, repeating the previous prompt isn't recorded again
+    pub fn append(&mut self, prompt: &str) -> Result<()> {
+        self.position = None;
+        if prompt.trim().is_empty() || self.entries.last().map(String::as_str) == Some(prompt) {
+            return Ok(());
+        }
+        self.entries.push(prompt.to_owned());
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("Failed to open {}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(prompt)?)
+            .context(format!("Failed to write {}", path.display()))
+    }
+
+    // the next older prompt, `current` is kept to come back to
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let position = match self.position {
+            None => {
+                self.draft = current.to_owned();
+                self.entries.len().checked_sub(1)?
+            }
+            Some(position) => position.checked_sub(1)?,
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    // the next newer prompt, or the draft after the newest one
+    pub fn newer(&mut self) -> Option<&str> {
+        let position = self.position?;
+        if position + 1 < self.entries.len() {
+            self.position = Some(position + 1);
+            Some(&self.entries[position + 1])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+// the root's path with everything but letters and digits replaced, e.g. _home_me_project.jsonl
+fn file_name(root: &Path) -> String {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let name: String = root
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}.jsonl", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_history_persists_and_cycles() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("history");
+
+        let mut history = History::load_from(path.clone());
+        history.append("first\nprompt").unwrap();
+        history.append("second").unwrap();
+        history.append("second").unwrap();
+
+        let mut history = History::load_from(path.clone());
+        assert_eq!(history.entries(), ["first\nprompt", "second"]);
+        assert_eq!(history.older("draft"), Some("second"));
+        assert_eq!(history.older("second"), Some("first\nprompt"));
+        assert_eq!(history.older("first\nprompt"), None);
+        assert_eq!(history.newer(), Some("second"));
+        assert_eq!(history.newer(), Some("draft"));
+        assert_eq!(history.newer(), None);
+    }
+}
@@ -1,11 +1,242 @@
-use crossterm::event::KeyEvent;
+use std::fmt;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Deserialize, Deserializer};
 
 pub enum Action {
     Send,
     Quit,
+    // suspend the ui and edit the prompt in $VISUAL or $EDITOR
+    EditPrompt,
 }
 
 pub trait InputHandler {
     fn process_key(&mut self, input: KeyEvent) -> Option<Action>;
     fn process_tick(&mut self);
+
+    // text pasted with bracketed paste arrives in one piece instead of as key presses
+    fn process_paste(&mut self, _text: &str) -> Option<Action> {
+        None
+    }
+}
+
+// A single key chord such as `j`, `enter` or `ctrl-h`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub const fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        Self { code, modifiers }
+    }
+
+    pub const fn plain(code: KeyCode) -> Self {
+        Self::new(code, KeyModifiers::NONE)
+    }
+
+    pub const fn ctrl(c: char) -> Self {
+        Self::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    pub fn matches(&self, input: &KeyEvent) -> bool {
+        // the case of a character already encodes shift, terminals disagree on reporting it
+        let ignored = match input.code {
+            KeyCode::Char(_) => KeyModifiers::SHIFT,
+            _ => KeyModifiers::NONE,
+        };
+        input.code == self.code
+            && input.modifiers.difference(ignored) == self.modifiers.difference(ignored)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        // a trailing '-' is the minus key itself, e.g. `ctrl--`
+        let (mods, key) = match s.strip_suffix("--") {
+            Some(mods) => (mods, "-"),
+            None if s == "-" => ("", "-"),
+            None => match s.rsplit_once('-') {
+                Some((mods, key)) => (mods, key),
+                None => ("", s),
+            },
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in mods.split('-').filter(|m| !m.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("unknown modifier `{}` in `{}`", modifier, s)),
+            };
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            // terminals report shifted letters in upper case
+            (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match key.to_lowercase().as_str() {
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "space" => KeyCode::Char(' '),
+                other => match other.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key `{}` in `{}`", key, s)),
+                },
+            },
+        };
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            code => write!(f, "{}", format!("{:?}", code).to_lowercase()),
+        }
+    }
+}
+
+// All the chords bound to one action, written as either a string or a list of strings
+#[derive(Clone, PartialEq, Debug)]
+pub struct KeyBindings(Vec<KeyBinding>);
+
+impl KeyBindings {
+    pub fn matches(&self, input: &KeyEvent) -> bool {
+        self.0.iter().any(|binding| binding.matches(input))
+    }
+}
+
+impl From<KeyBinding> for KeyBindings {
+    fn from(binding: KeyBinding) -> Self {
+        Self(vec![binding])
+    }
+}
+
+impl fmt::Display for KeyBindings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bindings: Vec<String> = self.0.iter().map(|b| b.to_string()).collect();
+        write!(f, "{}", bindings.join("/"))
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            One(String),
+            Many(Vec<String>),
+        }
+
+        let raw = match Raw::deserialize(deserializer)? {
+            Raw::One(binding) => vec![binding],
+            Raw::Many(bindings) => bindings,
+        };
+        if raw.is_empty() {
+            return Err(serde::de::Error::custom(
+                "expected at least one key binding",
+            ));
+        }
+        raw.iter()
+            .map(|binding| KeyBinding::parse(binding))
+            .collect::<Result<Vec<_>, _>>()
+            .map(KeyBindings)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// The configurable key bindings, shared by the app and the widgets
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keymap {
+    pub focus_left: KeyBindings,
+    pub focus_right: KeyBindings,
+    pub focus_up: KeyBindings,
+    pub focus_down: KeyBindings,
+    pub quit: KeyBindings,
+    pub up: KeyBindings,
+    pub down: KeyBindings,
+    pub left: KeyBindings,
+    pub right: KeyBindings,
+    pub select: KeyBindings,
+    pub undo: KeyBindings,
+    pub redo: KeyBindings,
+    pub external_editor: KeyBindings,
+    pub templates: KeyBindings,
+    pub history_search: KeyBindings,
+    pub system_prompt: KeyBindings,
+    pub toggle_instructions: KeyBindings,
+    pub file_finder: KeyBindings,
+    pub search: KeyBindings,
+    pub search_next: KeyBindings,
+    pub search_previous: KeyBindings,
+    pub toggle_hidden: KeyBindings,
+    pub toggle_ignored: KeyBindings,
+    pub select_lines: KeyBindings,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            focus_left: KeyBinding::ctrl('h').into(),
+            focus_right: KeyBinding::ctrl('l').into(),
+            focus_up: KeyBinding::ctrl('k').into(),
+            focus_down: KeyBinding::ctrl('j').into(),
+            quit: KeyBinding::ctrl('c').into(),
+            up: KeyBinding::plain(KeyCode::Char('k')).into(),
+            down: KeyBinding::plain(KeyCode::Char('j')).into(),
+            left: KeyBinding::plain(KeyCode::Char('h')).into(),
+            right: KeyBinding::plain(KeyCode::Char('l')).into(),
+            select: KeyBinding::plain(KeyCode::Enter).into(),
+            undo: KeyBinding::ctrl('z').into(),
+            redo: KeyBindings(vec![
+                KeyBinding::ctrl('y'),
+                KeyBinding::new(
+                    KeyCode::Char('Z'),
+                    KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+                ),
+            ]),
+            external_editor: KeyBinding::ctrl('e').into(),
+            templates: KeyBinding::ctrl('t').into(),
+            history_search: KeyBinding::ctrl('r').into(),
+            system_prompt: KeyBinding::ctrl('g').into(),
+            toggle_instructions: KeyBinding::ctrl('o').into(),
+            file_finder: KeyBinding::ctrl('p').into(),
+            search: KeyBinding::plain(KeyCode::Char('/')).into(),
+            search_next: KeyBinding::plain(KeyCode::Char('n')).into(),
+            search_previous: KeyBinding::plain(KeyCode::Char('N')).into(),
+            toggle_hidden: KeyBinding::plain(KeyCode::Char('.')).into(),
+            toggle_ignored: KeyBinding::plain(KeyCode::Char('i')).into(),
+            select_lines: KeyBinding::plain(KeyCode::Char('v')).into(),
+        }
+    }
 }
@@ -0,0 +1,81 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+// A project instructions file, e.g. .lope/instructions.md or AGENTS.md, sent with every query
+pub struct Instructions {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+// The instruction files in the roots and the directories up to their repository, outermost
+// first so the closest, most specific instructions come last. Unreadable and empty files
+// are skipped
+pub fn discover(roots: &[PathBuf], names: &[String]) -> Vec<Instructions> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let mut found: Vec<Instructions> = Vec::new();
+    for root in roots {
+        let root = root.canonicalize().unwrap_or_else(|_| root.clone());
+        let mut dirs = search_dirs(&root, home.as_deref());
+        dirs.reverse();
+        for dir in dirs {
+            for name in names {
+                let path = dir.join(name);
+                if found.iter().any(|instructions| instructions.path == path) {
+                    continue;
+                }
+                match fs::read_to_string(&path) {
+                    Ok(text) if !text.trim().is_empty() => found.push(Instructions { path, text }),
+                    _ => {}
+                }
+            }
+        }
+    }
+    found
+}
+
+// the root and the directories above it up to the top of the repository it's in. Outside a
+// repository, or when the home directory comes first, only the root is searched
+fn search_dirs<'a>(root: &'a Path, home: Option<&Path>) -> Vec<&'a Path> {
+    let mut dirs = Vec::new();
+    for dir in root.ancestors() {
+        if !dirs.is_empty() && home == Some(dir) {
+            break;
+        }
+        dirs.push(dir);
+        if dir.join(".git").exists() {
+            return dirs;
+        }
+    }
+    vec![root]
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_discover_walks_up_to_the_repository() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path().join("repo");
+        let project = repo.join("project");
+        fs::create_dir_all(project.join(".lope")).unwrap();
+        fs::create_dir(repo.join(".git")).unwrap();
+        fs::write(dir.path().join("AGENTS.md"), "outside the repository\n").unwrap();
+        fs::write(repo.join("AGENTS.md"), "be nice\n").unwrap();
+        fs::write(project.join(".lope/instructions.md"), "use anyhow\n").unwrap();
+        fs::write(project.join("AGENTS.md"), "  \n").unwrap();
+
+        let names = [".lope/instructions.md".to_owned(), "AGENTS.md".to_owned()];
+        let found = discover(&[project.clone(), project.clone()], &names);
+        let texts: Vec<&str> = found.iter().map(|found| found.text.as_str()).collect();
+        assert_eq!(texts, ["be nice\n", "use anyhow\n"]);
+
+        // outside a repository only the root itself is searched
+        fs::remove_dir(repo.join(".git")).unwrap();
+        assert_eq!(search_dirs(&project, Some(dir.path())), [project.as_path()]);
+    }
+}
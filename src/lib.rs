@@ -1,7 +1,23 @@
 pub mod app;
+pub mod buffer;
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod credentials;
 pub mod display;
+pub mod external_editor;
+pub mod file_text;
+pub mod fuzzy;
+pub mod highlight;
+pub mod history;
 pub mod input;
+pub mod instructions;
+pub mod mentions;
 pub mod openai;
 mod prompt;
+pub mod scanner;
+pub mod templates;
 pub mod theme;
+pub mod vim;
+pub mod watcher;
 pub mod widgets;
@@ -1,37 +1,75 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use clap::Parser;
+use crossbeam::{channel::never, select};
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture, Event as CEvent},
+    event::{
+        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+        Event as CEvent,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
-use std::{env, error::Error, fs, io, path::Path};
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io,
+    path::PathBuf,
+};
 
 use lope::{
-    app::{input_events, App, Event},
+    app::{input_events, App, Event, InputLock},
+    cli::{AskArgs, Cli, Command},
+    config::Config,
+    credentials::resolve_token,
     display::ui,
+    external_editor,
     input::{Action, InputHandler},
-    openai::call_gpt,
+    openai::{build_query, call_gpt, ContextSection, Query, Usage},
 };
+use serde::Serialize;
 
-const TOKEN_PATH: &str = ".sgpt/token";
+const TTY_PATH: &str = "/dev/tty";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let token = get_token()?;
+    let cli = Cli::parse();
+    let config = Config::load(&cli.project_roots()[0], &cli.config_overrides())?;
+    let token = resolve_token(&config.api)?;
+
+    match cli.command {
+        Some(Command::Ask(args)) => run_ask(&token, args, config).await,
+        None => run_tui(&token, &cli.roots, config).await,
+    }
+}
+
+async fn run_tui(token: &str, roots: &[PathBuf], config: Config) -> Result<(), Box<dyn Error>> {
+    check_roots(roots)?;
+
+    // Read piped input before taking over the terminal, e.g. `cargo build 2>&1 | lope`
+    let piped_input = read_piped_stdin()?;
 
     // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
+    // Draw to the controlling terminal directly so stdin and stdout are free to be pipes
+    let backend = CrosstermBackend::new(open_tty()?);
     let mut terminal = Terminal::new(backend)?;
+    resume_terminal(&mut terminal)?;
 
     // Create app state
-    let mut app = App::default();
+    let mut app = App::new(roots, config);
+    if let Some(input) = piped_input {
+        app.extra_context
+            .push(ContextSection::new("Standard Input", input));
+    }
 
     // Input events via crossbeam
-    let rx = input_events();
+    let input_lock = InputLock::default();
+    let rx = input_events(input_lock.clone());
+    // without a watcher the tree just doesn't refresh
+    let mut changes = app.watch_files().unwrap_or_else(|err| {
+        app.status = Some(format!("{:#}", err));
+        never()
+    });
 
     let exit_reason: Option<Action>;
 
@@ -39,48 +77,242 @@ async fn main() -> Result<(), Box<dyn Error>> {
     loop {
         terminal.draw(|f| ui(f, &mut app))?;
 
-        match rx.recv()? {
+        let event = select! {
+            recv(rx) -> event => event?,
+            recv(changes) -> dirs => match dirs {
+                Ok(dirs) => Event::FilesChanged(dirs),
+                Err(_) => {
+                    changes = never();
+                    Event::Tick
+                }
+            },
+        };
+        match event {
             Event::Input(event) => {
-                if let CEvent::Key(key_event) = event {
-                    if let Some(action) = app.process_key(key_event) {
-                        match action {
-                            Action::Send => {
-                                exit_reason = Some(Action::Send);
-                                break;
-                            }
-                            Action::Quit => {
-                                exit_reason = Some(Action::Quit);
-                                break;
+                let action = match event {
+                    CEvent::Key(key_event) => app.process_key(key_event),
+                    CEvent::Paste(text) => app.process_paste(&text),
+                    _ => None,
+                };
+                if let Some(action) = action {
+                    match action {
+                        Action::Send => {
+                            exit_reason = Some(Action::Send);
+                            break;
+                        }
+                        Action::Quit => {
+                            exit_reason = Some(Action::Quit);
+                            break;
+                        }
+                        Action::EditPrompt => {
+                            // keep the input thread off the terminal while the editor owns it
+                            let _paused = input_lock.pause();
+                            suspend_terminal(&mut terminal)?;
+                            // pasted blocks are opened expanded since markers can't be edited
+                            let edited =
+                                external_editor::edit(&app.active_editor().get_query_text());
+                            resume_terminal(&mut terminal)?;
+                            match edited {
+                                Ok(text) => {
+                                    app.active_editor().set_text(&text);
+                                    app.prompt_changed();
+                                    app.status = None;
+                                }
+                                Err(err) => app.status = Some(format!("{:#}", err)),
                             }
                         }
                     }
                 }
             }
             Event::Tick => app.process_tick(),
+            Event::FilesChanged(dirs) => app.refresh_dirs(&dirs),
         }
     }
 
     // Restore terminal
-    disable_raw_mode()?;
-    let stdout = terminal.backend_mut();
-    execute!(stdout, LeaveAlternateScreen, DisableMouseCapture)?;
-    terminal.show_cursor()?;
+    suspend_terminal(&mut terminal)?;
 
     if let Some(Action::Send) = exit_reason {
-        println!("{}", call_gpt(token.as_str(), &app).await?)
+        // the whole tree is sent, not just what was scanned so far
+        app.file_browser_state.finish_scan();
+        save_history(&mut app);
+        let query = build_query(&app);
+        report_left_out(&query);
+        println!("{}", call_gpt(token, &app, &query).await?.content())
     }
 
     Ok(())
 }
 
-// fetch the token from the filesystem
-fn get_token() -> Result<String, Box<dyn Error>> {
-    let home_dir = env::var("HOME")?;
-    let token_path = Path::new(&home_dir).join(TOKEN_PATH);
-    let token = fs::read_to_string(&token_path).context(format!(
-        "Failed to read token from {}",
-        token_path.to_string_lossy()
-    ))?;
-    let token = token.strip_suffix('\n').unwrap_or(&token);
-    Ok(token.to_owned())
+type Tui = Terminal<CrosstermBackend<File>>;
+
+// take over the terminal for drawing the ui
+fn resume_terminal(terminal: &mut Tui) -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
+    terminal.clear()
+}
+
+// hand the terminal back, either on exit or while an external editor runs
+fn suspend_terminal(terminal: &mut Tui) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableBracketedPaste
+    )?;
+    terminal.show_cursor()
+}
+
+// json output of the headless mode
+#[derive(Serialize)]
+struct AskOutput<'a> {
+    model: &'a str,
+    answer: &'a str,
+    files: Vec<&'a str>,
+    // included files that weren't sent, or only in part
+    skipped: &'a [String],
+    truncated: &'a [String],
+    usage: Option<&'a Usage>,
+}
+
+// run a single query without the ui, printing the answer to stdout
+async fn run_ask(token: &str, args: AskArgs, config: Config) -> Result<(), Box<dyn Error>> {
+    let piped_input = read_piped_stdin()?;
+
+    // the prompt comes from the flag if given, otherwise from stdin
+    let (prompt, piped_context) = match args.prompt {
+        Some(prompt) => (prompt, piped_input),
+        None => match piped_input {
+            Some(input) => (input, None),
+            None => {
+                return Err(anyhow!("No prompt given, pass --prompt or pipe one into stdin").into())
+            }
+        },
+    };
+
+    check_roots(&args.roots)?;
+    let mut app = App::new(&args.roots, config);
+    app.file_browser_state.finish_scan();
+    app.prompt_editor_state.set_text(&prompt);
+    for path in &args.files {
+        if !app.file_browser_state.include_path(path) {
+            return Err(anyhow!(
+                "{} is not a file in the project tree (it may be ignored)",
+                path.display()
+            )
+            .into());
+        }
+    }
+    if let Some(input) = piped_context {
+        app.extra_context
+            .push(ContextSection::new("Standard Input", input));
+    }
+
+    save_history(&mut app);
+    let query = build_query(&app);
+    report_left_out(&query);
+    let completion = call_gpt(token, &app, &query).await?;
+    if args.json {
+        let output = AskOutput {
+            model: &completion.model,
+            answer: completion.content(),
+            files: app
+                .file_browser_state
+                .get_included_entries()
+                .iter()
+                .map(|entry| entry.display_path.as_str())
+                .collect(),
+            skipped: &query.skipped,
+            truncated: &query.truncated,
+            usage: completion.usage.as_ref(),
+        };
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        println!("{}", completion.content());
+    }
+
+    Ok(())
+}
+
+// files left out of the query are reported on stderr, so the answer on stdout stays clean
+fn report_left_out(query: &Query) {
+    if let Some(report) = query.report() {
+        eprintln!("{}", report);
+    }
+}
+
+// a failure to record the prompt shouldn't stop it from being sent
+fn save_history(app: &mut App) {
+    let prompt = app.prompt_editor_state.get_query_text();
+    if let Err(err) = app.history.append(&prompt) {
+        eprintln!("Failed to save the prompt history: {:#}", err);
+    }
+}
+
+// every project root must be an existing directory
+fn check_roots(roots: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    for root in roots {
+        if !root.is_dir() {
+            return Err(anyhow!("{} is not a directory", root.display()).into());
+        }
+    }
+    Ok(())
+}
+
+// only a pipe or a redirected file is read, stdin left open by an IDE or a process
+// supervisor (a socket or a device) would block forever before the ui starts
+#[cfg(unix)]
+fn stdin_is_piped(stdin: &io::Stdin) -> bool {
+    use std::os::{fd::AsFd, unix::fs::FileTypeExt};
+
+    let Ok(fd) = stdin.as_fd().try_clone_to_owned() else {
+        return false;
+    };
+    File::from(fd)
+        .metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_fifo() || metadata.is_file())
+}
+
+#[cfg(not(unix))]
+fn stdin_is_piped(stdin: &io::Stdin) -> bool {
+    use std::io::IsTerminal;
+
+    !stdin.is_terminal()
+}
+
+// read all of stdin if it is being piped into lope, None if stdin is the terminal
+fn read_piped_stdin() -> Result<Option<String>, Box<dyn Error>> {
+    use std::io::Read;
+
+    let mut stdin = io::stdin();
+    if !stdin_is_piped(&stdin) {
+        return Ok(None);
+    }
+
+    let mut bytes = Vec::new();
+    stdin
+        .read_to_end(&mut bytes)
+        .context("Failed to read from stdin")?;
+    let input = String::from_utf8_lossy(&bytes).into_owned();
+    if input.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(input))
+}
+
+// open the controlling terminal for drawing the ui
+fn open_tty() -> Result<File, Box<dyn Error>> {
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(TTY_PATH)
+        .context(format!("Failed to open {}", TTY_PATH))?;
+    Ok(tty)
 }
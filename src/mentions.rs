@@ -0,0 +1,104 @@
+use std::ops::{Range, RangeInclusive};
+
+// An `@path` or `@path:start-end` reference to a file in the prompt text, several ranges
+// are separated by commas as in `@path:10-20,42`
+#[derive(Debug, PartialEq)]
+pub struct Mention {
+    pub path: String,
+    // 1-based inclusive line ranges, the whole file when empty
+    pub lines: Vec<RangeInclusive<usize>>,
+    // byte range of the mention in the text, including the `@`
+    pub range: Range<usize>,
+}
+
+// Every mention in the text, an `@` only starts one at the start of a word so
+// email addresses are left alone
+pub fn parse(text: &str) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+    let mut word_start = true;
+    for (idx, c) in text.char_indices() {
+        if c == '@' && word_start {
+            let end = token_end(text, idx);
+            let (path, lines) = split_line_ranges(&text[idx + 1..end]);
+            if !path.is_empty() {
+                mentions.push(Mention {
+                    path: path.to_owned(),
+                    lines,
+                    range: idx..end,
+                });
+            }
+        }
+        word_start = c.is_whitespace() || c == '(';
+    }
+    mentions
+}
+
+// The mention the cursor is at the end of, with the path typed so far. Used for completion,
+// so mentions that already have a line range don't count
+pub fn at_cursor(text: &str, cursor: usize) -> Option<(Range<usize>, &str)> {
+    let start = text[..cursor]
+        .rfind(|c: char| c.is_whitespace() || c == '(')
+        .map_or(0, |idx| {
+            idx + text[idx..].chars().next().map_or(1, char::len_utf8)
+        });
+    let token = &text[start..cursor];
+    let path = token.strip_prefix('@')?;
+    if path.contains([':', '@']) || token_end(text, start) != cursor {
+        return None;
+    }
+    Some((start..cursor, path))
+}
+
+// mentions end at whitespace or trailing punctuation like `,` or `)`
+fn token_end(text: &str, start: usize) -> usize {
+    let end = text[start..]
+        .find(|c: char| c.is_whitespace())
+        .map_or(text.len(), |idx| start + idx);
+    start
+        + text[start..end]
+            .trim_end_matches([',', ';', ')', '?', '!'])
+            .len()
+}
+
+// Split a `path:start-end` spec into the path and its line ranges, a spec that isn't
+// a valid list of ranges is taken as part of the path
+pub fn split_line_ranges(token: &str) -> (&str, Vec<RangeInclusive<usize>>) {
+    let Some((path, spec)) = token.rsplit_once(':') else {
+        return (token.trim_end_matches('.'), Vec::new());
+    };
+    let ranges: Option<Vec<RangeInclusive<usize>>> = spec
+        .split(',')
+        .map(|range| {
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                None => (range.parse().ok()?, range.parse().ok()?),
+            };
+            (0 < start && start <= end).then_some(start..=end)
+        })
+        .collect();
+    match ranges {
+        Some(ranges) => (path, ranges),
+        None => (token, Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mentions() {
+        let text = "look at @src/app.rs:80-110,5, and (@README.md) not me@example.com @";
+        let mentions = parse(text);
+        assert_eq!(mentions.len(), 2);
+        assert_eq!(mentions[0].path, "src/app.rs");
+        assert_eq!(mentions[0].lines, vec![80..=110, 5..=5]);
+        assert_eq!(&text[mentions[0].range.clone()], "@src/app.rs:80-110,5");
+        assert_eq!(mentions[1].path, "README.md");
+        assert!(mentions[1].lines.is_empty());
+
+        assert_eq!(at_cursor("see @src/wi", 11), Some((4..11, "src/wi")));
+        assert_eq!(at_cursor("see @src/app.rs:1", 17), None);
+        assert_eq!(at_cursor("see src", 7), None);
+    }
+}
@@ -1,19 +1,63 @@
-use anyhow::{Context, Result};
+use std::{borrow::Cow, path::Path};
+
+use anyhow::{bail, Context, Result};
 use reqwest;
 use serde::{Deserialize, Serialize};
 
-use crate::{app::App, prompt::SYSTEM_PROMPT};
+use crate::{app::App, file_text, prompt::QUERY_FORMAT, widgets::file_browser::TreeEntry};
 
 const COMPLETION_ENDPOINT: &str = "https://api.openai.com/v1/chat/completions";
 
-pub async fn call_gpt(token: &str, app: &App) -> Result<String> {
-    let prompt_text = app.prompt_editor_state.get_display_text();
+pub async fn call_gpt(token: &str, app: &App, query: &Query) -> Result<Completion> {
+    complete(
+        token,
+        &app.config.api.model,
+        &build_system_prompt(app),
+        &query.text,
+    )
+    .await
+}
+
+// the session's system prompt followed by the project instructions, if they are on
+pub fn build_system_prompt(app: &App) -> String {
+    let mut prompt = app.system_prompt().trim_end().to_owned();
+    if app.instructions_enabled {
+        for instructions in &app.instructions {
+            prompt.push_str(&format!(
+                "\n\n### Project Instructions ({}):\n{}",
+                instructions.path.display(),
+                instructions.text.trim_end()
+            ));
+        }
+    }
+    prompt
+}
+
+// build the full query from the current state of the app
+pub fn build_query(app: &App) -> Query {
+    build_query_with_prompt(app, &app.prompt_editor_state.get_query_text())
+}
+
+// the query with a different prompt, e.g. none when exporting the context
+pub fn build_query_with_prompt(app: &App, prompt_text: &str) -> Query {
     let tree = app.file_browser_state.get_entire_tree();
-    let file_paths = app.file_browser_state.get_included_entries();
-    let query =
-        construct_query(prompt_text, &tree, &file_paths).context("Failed to construct query")?;
+    let files = app.file_browser_state.get_included_entries();
+    construct_query(
+        prompt_text,
+        &tree,
+        &files,
+        &app.extra_context,
+        app.config.prompt.max_sent_bytes,
+    )
+}
 
-    let request_payload = Chat::new_from_query(&query);
+pub async fn complete(
+    token: &str,
+    model: &str,
+    system_prompt: &str,
+    query: &str,
+) -> Result<Completion> {
+    let request_payload = Chat::new_from_query(model, system_prompt, query);
 
     let client = reqwest::Client::new();
     let resp = client
@@ -23,33 +67,144 @@ pub async fn call_gpt(token: &str, app: &App) -> Result<String> {
         .send()
         .await?;
 
-    if resp.status() != 200 {
-        println!("Error: {}", resp.status());
-        println!("Error: {}", resp.text().await?);
-        return Ok("".to_owned());
+    let status = resp.status();
+    let resp_text = resp.text().await?;
+    if status != 200 {
+        bail!("Request failed with status {}: {}", status, resp_text);
     }
 
-    let resp_text = resp.text().await?;
-    let completion: Completion = serde_json::from_str(&resp_text)?;
-    let response_text = completion.choices.first().unwrap().message.content.clone();
+    let completion: Completion =
+        serde_json::from_str(&resp_text).context("Failed to parse completion response")?;
+    if completion.choices.is_empty() {
+        bail!("Completion response contained no choices");
+    }
+
+    Ok(completion)
+}
+
+// Additional context that doesn't come from the file browser, e.g. piped stdin
+pub struct ContextSection {
+    pub title: String,
+    pub body: String,
+}
+
+impl ContextSection {
+    pub fn new(title: &str, body: String) -> Self {
+        Self {
+            title: title.to_owned(),
+            body,
+        }
+    }
+}
 
-    Ok(response_text)
+// The text sent to the model, along with the included files that couldn't be sent whole
+pub struct Query {
+    pub text: String,
+    // each with the reason, e.g. `crate/logo.png (binary file)`
+    pub skipped: Vec<String>,
+    pub truncated: Vec<String>,
+}
+
+impl Query {
+    // what was left out of the query, for the user, None if nothing was
+    pub fn report(&self) -> Option<String> {
+        let mut report = Vec::new();
+        if !self.skipped.is_empty() {
+            report.push(format!("Skipped {}", self.skipped.join(", ")));
+        }
+        if !self.truncated.is_empty() {
+            report.push(format!("Truncated {}", self.truncated.join(", ")));
+        }
+        (!report.is_empty()).then(|| report.join("; "))
+    }
 }
 
-fn construct_query(query: &str, tree: &str, file_paths: &[String]) -> Result<String> {
+// a file that can't be read or isn't text is skipped rather than failing the whole query
+fn construct_query(
+    query: &str,
+    tree: &str,
+    files: &[&TreeEntry],
+    extra_context: &[ContextSection],
+    max_sent_bytes: usize,
+) -> Query {
     // user query
-    let mut query = format!("{}\n\n", query);
+    let mut text = format!("{}\n\n", query);
 
     // file system hierarchy
-    query.push_str(format!("### File Tree:\n{}\n\n", tree).as_str());
+    text.push_str(format!("### File Tree:\n{}\n\n", tree).as_str());
+
+    text.push_str("### File Contents:\n");
+    let (mut skipped, mut truncated) = (Vec::new(), Vec::new());
+    for file in files {
+        let path = Path::new(&file.path);
+        // whole files are only read as far as they're sent, line ranges need every line
+        let read = match file.line_ranges.is_empty() {
+            true => file_text::read_truncated(path, max_sent_bytes),
+            false => file_text::read(path).map(|read| read.map(|read| (read.text, false))),
+        };
+        let (file_contents, was_truncated) = match read {
+            Ok(Some(read)) => read,
+            Ok(None) => {
+                skipped.push(format!("{} (binary file)", file.display_path));
+                continue;
+            }
+            Err(err) => {
+                skipped.push(format!("{} ({:#})", file.display_path, err));
+                continue;
+            }
+        };
+
+        if file.line_ranges.is_empty() {
+            if was_truncated {
+                truncated.push(file.display_path.clone());
+            }
+            text.push_str(&format!(
+                "```\n// {}\n{}\n```\n",
+                file.display_path, file_contents
+            ));
+            continue;
+        }
+        let lines: Vec<&str> = file_contents.lines().collect();
+        for range in &file.line_ranges {
+            // ranges are labelled with the lines they cover, the file may have shrunk
+            let end = (*range.end()).min(lines.len());
+            if *range.start() > end {
+                skipped.push(format!(
+                    "{}:{}-{} (past the end of the file)",
+                    file.display_path,
+                    range.start(),
+                    range.end()
+                ));
+                continue;
+            }
+            let label = format!("{}:{}-{}", file.display_path, range.start(), end);
+            let contents = lines[range.start() - 1..end].join("\n");
+            let contents = file_text::truncate(&contents, max_sent_bytes);
+            if let Cow::Owned(_) = contents {
+                truncated.push(label.clone());
+            }
+            text.push_str(&format!("```\n// {}\n{}\n```\n", label, contents));
+        }
+    }
+    if !skipped.is_empty() {
+        text.push_str("\n### Skipped Files:\n");
+        for file in &skipped {
+            text.push_str(&format!("- {}\n", file));
+        }
+    }
 
-    query.push_str("### File Contents:\n");
-    for path in file_paths {
-        let file_contents =
-            std::fs::read_to_string(path).context(format!("Failed to read file: {}", path))?;
-        query.push_str(&format!("```\n// {}\n{}\n```\n", path, file_contents));
+    for section in extra_context {
+        text.push_str(&format!(
+            "\n### {}:\n```\n{}\n```\n",
+            section.title,
+            section.body.trim_end()
+        ));
+    }
+    Query {
+        text,
+        skipped,
+        truncated,
     }
-    Ok(query)
 }
 
 #[derive(Serialize, Deserialize)]
@@ -59,13 +214,13 @@ struct Chat {
 }
 
 impl Chat {
-    fn new_from_query(query: &str) -> Self {
+    fn new_from_query(model: &str, system_prompt: &str, query: &str) -> Self {
         Chat {
-            model: "gpt-4o-2024-11-20".to_owned(),
+            model: model.to_owned(),
             messages: vec![
                 Message {
                     role: Role::System,
-                    content: SYSTEM_PROMPT.to_owned(),
+                    content: format!("{}\n\n{}", system_prompt.trim_end(), QUERY_FORMAT),
                 },
                 Message {
                     role: Role::User,
@@ -77,9 +232,26 @@ impl Chat {
 }
 
 #[derive(Serialize, Deserialize)]
-struct Completion {
-    model: String,
+pub struct Completion {
+    pub model: String,
     choices: Vec<Choice>,
+    pub usage: Option<Usage>,
+}
+
+impl Completion {
+    pub fn content(&self) -> &str {
+        self.choices
+            .first()
+            .map(|choice| choice.message.content.as_str())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -105,17 +277,64 @@ enum Role {
 
 #[cfg(test)]
 mod tests {
+    use tempfile::TempDir;
+
     use super::*;
+    use crate::prompt::SYSTEM_PROMPT;
 
     #[test]
     fn test_chat_new_from_query() {
         let query = "hi, how are you doing?";
-        let chat = Chat::new_from_query(query);
-        assert_eq!(chat.model, "gpt-4");
+        let chat = Chat::new_from_query("gpt-4o-2024-11-20", SYSTEM_PROMPT, query);
+        assert_eq!(chat.model, "gpt-4o-2024-11-20");
         assert_eq!(chat.messages.len(), 2);
         assert_eq!(chat.messages[0].role, Role::System);
-        assert_eq!(chat.messages[0].content, SYSTEM_PROMPT);
+        assert!(chat.messages[0]
+            .content
+            .starts_with(SYSTEM_PROMPT.trim_end()));
+        assert!(chat.messages[0].content.ends_with(QUERY_FORMAT));
         assert_eq!(chat.messages[1].role, Role::User);
         assert_eq!(chat.messages[1].content, query);
     }
+
+    #[test]
+    fn test_construct_query_with_extra_context() {
+        let extra = vec![ContextSection::new(
+            "Standard Input",
+            "error[E0308]: mismatched types\n".to_owned(),
+        )];
+        let query = construct_query("why?", "./\n", &[], &extra, 100_000).text;
+        assert!(query.starts_with("why?\n\n### File Tree:\n"));
+        assert!(query.ends_with("### Standard Input:\n```\nerror[E0308]: mismatched types\n```\n"));
+    }
+
+    #[test]
+    fn test_construct_query_with_line_ranges() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one\ntwo\nthree\nfour\n").unwrap();
+        let mut file = TreeEntry::new(
+            path.display().to_string(),
+            "crate/notes.txt".to_owned(),
+            1,
+            false,
+        );
+        file.line_ranges = vec![2..=2, 4..=9, 12..=14];
+        let missing = TreeEntry::new(
+            "/nonexistent/lope".to_owned(),
+            "crate/gone.txt".to_owned(),
+            1,
+            false,
+        );
+        let query = construct_query("why?", "", &[&file, &missing], &[], 100_000);
+        assert_eq!(query.skipped.len(), 2);
+        assert_eq!(
+            query.skipped[0],
+            "crate/notes.txt:12-14 (past the end of the file)"
+        );
+        assert!(query.skipped[1].starts_with("crate/gone.txt (Failed to read"));
+        let query = query.text;
+        assert!(query.contains("```\n// crate/notes.txt:2-2\ntwo\n```\n"));
+        assert!(query.contains("```\n// crate/notes.txt:4-4\nfour\n```\n"));
+    }
 }
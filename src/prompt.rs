@@ -8,8 +8,10 @@ Never suggest seeking information from elsewhere.
 Always focus on the key points in my questions to determine my intent. 
 Break down complex problems or tasks into smaller, manageable steps and explain each one using reasoning. 
 Provide multiple perspectives or solutions. 
+";
 
-The messages you receive will usually be of the following format:
+// Appended to every system prompt, personas included, so the model knows how queries are laid out
+pub const QUERY_FORMAT: &str = "The messages you receive will usually be of the following format:
 
 <user message explaining the problem>
 
@@ -28,5 +30,13 @@ information about the file contents, for example:
 <source code for file 2>
 
 ... etc
+```
+the middle of a very long file may be left out, which is marked in its place
+
+### Skipped Files:
+included files that couldn't be sent, e.g. binary files, with the reason (only present when there are any)
+
+### Standard Input:
+output that was piped into the tool, for example compiler errors (only present when provided)
 
 ";
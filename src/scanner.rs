@@ -0,0 +1,242 @@
+use std::{
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{unbounded, Receiver};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{DirEntry, WalkBuilder};
+
+use crate::{
+    config::TreeConfig,
+    widgets::file_browser::{DirectoryState, EntryType, TreeEntry},
+};
+
+// how often the background scan hands what it found so far to the ui
+const BATCH_INTERVAL: Duration = Duration::from_millis(100);
+// gitignore style rules for lope only, in any directory of the project
+const LOPE_IGNORE: &str = ".lopeignore";
+
+// Which entries are scanned, built from the [tree] config
+#[derive(Clone)]
+pub struct Filters {
+    include: GlobSet,
+    exclude: GlobSet,
+    pub hidden: bool,
+    pub ignored: bool,
+    max_depth: Option<usize>,
+    max_file_size: Option<u64>,
+}
+
+impl Filters {
+    // the config's globs were checked when it was loaded
+    pub fn new(config: &TreeConfig) -> Self {
+        Self {
+            include: glob_set(&config.include),
+            exclude: glob_set(&config.exclude),
+            hidden: config.hidden,
+            ignored: config.ignored,
+            max_depth: config.max_depth,
+            max_file_size: config.max_file_size,
+        }
+    }
+
+    // whether the entry at a path relative to its root is shown
+    fn allows(&self, rel: &Path, is_dir: bool) -> bool {
+        if self
+            .max_depth
+            .is_some_and(|max_depth| rel.components().count() > max_depth)
+        {
+            return false;
+        }
+        if self.exclude.is_match(rel) {
+            return false;
+        }
+        is_dir || self.include.is_empty() || self.include.is_match(rel)
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self::new(&TreeConfig::default())
+    }
+}
+
+fn glob_set(globs: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs.iter().filter_map(|glob| Glob::new(glob).ok()) {
+        builder.add(glob);
+    }
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+// Entries found by the background scan of one root, in path order
+pub struct Batch {
+    pub root: String,
+    pub entries: Vec<TreeEntry>,
+    // directories whose children have all been sent
+    pub completed: Vec<String>,
+}
+
+// The entry shown for a project root, its children are listed with `list_children`
+pub fn root_entry(root: &Path, label: &str) -> TreeEntry {
+    TreeEntry::new(root.display().to_string(), label.to_owned(), 0, true)
+}
+
+// The entries directly inside a directory under a project root
+pub fn list_children(root: &Path, label: &str, dir: &Path, filters: &Filters) -> Vec<TreeEntry> {
+    walker(root, dir, filters)
+        .max_depth(Some(1))
+        .build()
+        .flatten()
+        .filter(|entry| entry.depth() > 0)
+        .filter_map(|entry| tree_entry(root, label, &entry))
+        .collect()
+}
+
+// Walk every root in a background thread, the receiver is disconnected once all are done
+pub fn scan_in_background(roots: Vec<(PathBuf, String)>, filters: Filters) -> Receiver<Batch> {
+    let (tx, rx) = unbounded();
+    thread::spawn(move || {
+        for (root, label) in roots {
+            let root_path = root.display().to_string();
+            let new_batch = || Batch {
+                root: root_path.clone(),
+                entries: Vec::new(),
+                completed: Vec::new(),
+            };
+            let mut batch = new_batch();
+            let mut sent = Instant::now();
+            // directories the walk is still inside of, a directory is complete once it leaves
+            let mut open_dirs: Vec<(usize, String)> = vec![(0, root_path.clone())];
+
+            for entry in walker(&root, &root, &filters)
+                .build()
+                .flatten()
+                .filter(|e| e.depth() > 0)
+            {
+                let Some(entry) = tree_entry(&root, &label, &entry) else {
+                    continue;
+                };
+                while open_dirs
+                    .last()
+                    .is_some_and(|(depth, _)| *depth >= entry.depth)
+                {
+                    batch
+                        .completed
+                        .extend(open_dirs.pop().map(|(_, path)| path));
+                }
+                if !entry.is_file() {
+                    open_dirs.push((entry.depth, entry.path.clone()));
+                }
+                batch.entries.push(entry);
+
+                if sent.elapsed() >= BATCH_INTERVAL {
+                    if tx.send(std::mem::replace(&mut batch, new_batch())).is_err() {
+                        return;
+                    }
+                    sent = Instant::now();
+                }
+            }
+            batch
+                .completed
+                .extend(open_dirs.into_iter().rev().map(|(_, path)| path));
+            if tx.send(batch).is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+// .gitignore rules are enabled unless ignored files are shown, siblings are sorted by name
+// so every scan of a directory lists its entries in the same order
+fn walker(root: &Path, dir: &Path, filters: &Filters) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(dir);
+    builder
+        .standard_filters(true)
+        .hidden(!filters.hidden)
+        .ignore(!filters.ignored)
+        .git_ignore(!filters.ignored)
+        .git_global(!filters.ignored)
+        .git_exclude(!filters.ignored)
+        .add_custom_ignore_filename(LOPE_IGNORE)
+        .sort_by_file_name(|a, b| a.cmp(b));
+
+    let (root, filters) = (root.to_path_buf(), filters.clone());
+    // the walker's own max_filesize skips this filter for files, so sizes are checked here
+    builder.filter_entry(move |entry| {
+        let rel = entry.path().strip_prefix(&root).unwrap_or(Path::new(""));
+        let is_dir = entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_dir());
+        let too_large = !is_dir
+            && filters.max_file_size.is_some_and(|max_file_size| {
+                entry
+                    .metadata()
+                    .is_ok_and(|metadata| metadata.len() > max_file_size)
+            });
+        // the repository's internals aren't project files, even with hidden files shown
+        entry.file_name() != ".git" && !too_large && filters.allows(rel, is_dir)
+    });
+    builder
+}
+
+// each entry is displayed relative to the root's label, directories start out collapsed
+// and their children are only known once they are scanned
+fn tree_entry(root: &Path, label: &str, entry: &DirEntry) -> Option<TreeEntry> {
+    let is_dir = entry.file_type()?.is_dir();
+    let path = entry.path().display().to_string();
+    let rel = entry.path().strip_prefix(root).unwrap_or(Path::new(""));
+    let display_path = match rel.as_os_str().is_empty() {
+        false => format!("{}/{}", label, rel.display()),
+        true => label.to_owned(),
+    };
+
+    let mut tree_entry = TreeEntry::new(path, display_path, rel.components().count(), is_dir);
+    if is_dir {
+        tree_entry.entry_type = EntryType::Directory(DirectoryState {
+            expanded: false,
+            loaded: false,
+        });
+    }
+    Some(tree_entry)
+}
+
+// The name a root is shown under, its directory name unless another root already uses it
+pub fn root_label(root: &Path, taken: &[String]) -> String {
+    let canonical = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let name = canonical
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| canonical.display().to_string());
+    if taken.contains(&name) {
+        canonical.display().to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_allow() {
+        let filters = Filters::new(&TreeConfig {
+            include: vec!["*.rs".to_owned(), "README.md".to_owned()],
+            exclude: vec!["*.lock".to_owned(), "vendor".to_owned()],
+            max_depth: Some(2),
+            ..Default::default()
+        });
+        assert!(filters.allows(Path::new("src/app.rs"), false));
+        assert!(filters.allows(Path::new("README.md"), false));
+        // directories are walked even though only files are included by name
+        assert!(filters.allows(Path::new("src"), true));
+        assert!(!filters.allows(Path::new("notes.txt"), false));
+        assert!(!filters.allows(Path::new("fuzz/Cargo.lock"), false));
+        assert!(!filters.allows(Path::new("vendor"), true));
+        assert!(!filters.allows(Path::new("src/widgets/options.rs"), false));
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::config;
+
+const TEMPLATE_DIR: &str = "templates";
+const PROJECT_TEMPLATE_DIR: &str = ".lope/templates";
+
+// A named prompt stored as a file in one of the template directories
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    pub name: String,
+    pub body: String,
+}
+
+// Part of a template, either literal text or a {{placeholder}}
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Selection,
+    FileList,
+    GitBranch,
+    // free-form question answered when the template is inserted
+    Ask(String),
+}
+
+// Values for the placeholders that are filled in without asking
+#[derive(Default)]
+pub struct Variables {
+    pub selection: String,
+    pub file_list: String,
+    pub git_branch: String,
+}
+
+// Templates from the user directory and the project's .lope/templates, project
+// templates replace user templates with the same name
+pub fn load(root: &Path) -> Result<Vec<Template>> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = config::user_config_dir() {
+        dirs.push(dir.join(TEMPLATE_DIR));
+    }
+    if let Some(dir) = project_template_dir(root) {
+        dirs.push(dir);
+    }
+    load_from(&dirs)
+}
+
+// every file in the given directories is a template named after its file stem
+pub fn load_from(dirs: &[PathBuf]) -> Result<Vec<Template>> {
+    let mut templates = BTreeMap::new();
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        let entries =
+            fs::read_dir(dir).context(format!("Failed to read templates in {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let Some(name) = path
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+            else {
+                continue;
+            };
+            let body = fs::read_to_string(&path)
+                .context(format!("Failed to read template {}", path.display()))?;
+            templates.insert(name.clone(), Template { name, body });
+        }
+    }
+    Ok(templates.into_values().collect())
+}
+
+// the closest .lope/templates in the root or one of its parents
+fn project_template_dir(root: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    root.ancestors()
+        .map(|dir| dir.join(PROJECT_TEMPLATE_DIR))
+        .find(|dir| dir.is_dir())
+}
+
+impl Template {
+    pub fn parse(&self) -> Result<Vec<Segment>> {
+        let mut segments = Vec::new();
+        let mut rest = self.body.as_str();
+        while let Some(open) = rest.find("{{") {
+            let Some(close) = rest[open..].find("}}") else {
+                bail!("Unclosed {{{{ in template `{}`", self.name);
+            };
+            if open > 0 {
+                segments.push(Segment::Text(rest[..open].to_owned()));
+            }
+            let placeholder = rest[open + 2..open + close].trim();
+            segments.push(match placeholder {
+                "selection" => Segment::Selection,
+                "file_list" => Segment::FileList,
+                "git_branch" => Segment::GitBranch,
+                _ => match placeholder.strip_prefix("ask:") {
+                    Some(question) => Segment::Ask(question.trim().to_owned()),
+                    None => bail!(
+                        "Unknown placeholder {{{{{}}}}} in template `{}`",
+                        placeholder,
+                        self.name
+                    ),
+                },
+            });
+            rest = &rest[open + close + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+        Ok(segments)
+    }
+}
+
+// the questions to ask before the template can be filled in, each asked once
+pub fn questions(segments: &[Segment]) -> Vec<String> {
+    let mut questions: Vec<String> = Vec::new();
+    for segment in segments {
+        if let Segment::Ask(question) = segment {
+            if !questions.contains(question) {
+                questions.push(question.clone());
+            }
+        }
+    }
+    questions
+}
+
+// fill in the placeholders, `answers` line up with `questions(segments)`
+pub fn render(segments: &[Segment], variables: &Variables, answers: &[String]) -> String {
+    let questions = questions(segments);
+    segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.as_str(),
+            Segment::Selection => &variables.selection,
+            Segment::FileList => &variables.file_list,
+            Segment::GitBranch => &variables.git_branch,
+            Segment::Ask(question) => questions
+                .iter()
+                .position(|asked| asked == question)
+                .and_then(|idx| answers.get(idx))
+                .map_or("", String::as_str),
+        })
+        .collect()
+}
+
+// the checked out branch of the repository containing `dir`, empty outside of git
+pub fn git_branch(dir: &Path) -> String {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_fills_placeholders() {
+        let template = Template {
+            name: "bug".to_owned(),
+            body: "On {{git_branch}}: {{ ask:Describe the bug }}\n{{selection}}\n{{file_list}}\n{{ask:Describe the bug}}".to_owned(),
+        };
+        let segments = template.parse().unwrap();
+        assert_eq!(questions(&segments), vec!["Describe the bug"]);
+
+        let variables = Variables {
+            selection: "fn main() {}".to_owned(),
+            file_list: "crate/src/main.rs".to_owned(),
+            git_branch: "main".to_owned(),
+        };
+        assert_eq!(
+            render(&segments, &variables, &["it panics".to_owned()]),
+            "On main: it panics\nfn main() {}\ncrate/src/main.rs\nit panics"
+        );
+
+        let template = Template {
+            name: "typo".to_owned(),
+            body: "{{selction}}".to_owned(),
+        };
+        assert!(template.parse().is_err());
+    }
+}
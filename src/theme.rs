@@ -1,4 +1,5 @@
 use ratatui::style::Color;
+use serde::{Deserialize, Deserializer};
 
 pub const GRAY: ratatui::style::Color = Color::Rgb(21, 21, 21);
 pub const LIGHT_GREY: ratatui::style::Color = Color::Rgb(41, 41, 41);
@@ -6,3 +7,61 @@ pub const LIGHT_GREEN: ratatui::style::Color = Color::Rgb(135, 175, 135);
 pub const YELLOW: ratatui::style::Color = Color::Rgb(248, 242, 136);
 pub const PURPLE: ratatui::style::Color = Color::Rgb(95, 95, 135);
 pub const BLUE: ratatui::style::Color = Color::Rgb(95, 135, 175);
+pub const PINK: ratatui::style::Color = Color::Rgb(215, 135, 175);
+pub const GOLD: ratatui::style::Color = Color::Rgb(215, 175, 95);
+pub const SKY_BLUE: ratatui::style::Color = Color::Rgb(95, 175, 215);
+pub const MID_GREY: ratatui::style::Color = Color::Rgb(108, 108, 108);
+
+// Colors used when drawing the ui, configurable through the [theme] table
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    #[serde(deserialize_with = "deserialize_color")]
+    pub background: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub highlight: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub selected: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub text: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub send: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub quit: Color,
+    // syntax highlighting in the file preview
+    #[serde(deserialize_with = "deserialize_color")]
+    pub keyword: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub string: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub number: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub comment: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: GRAY,
+            highlight: LIGHT_GREY,
+            selected: YELLOW,
+            text: LIGHT_GREEN,
+            send: BLUE,
+            quit: PURPLE,
+            keyword: PINK,
+            string: GOLD,
+            number: SKY_BLUE,
+            comment: MID_GREY,
+        }
+    }
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(|_| {
+        serde::de::Error::custom(format!(
+            "invalid color `{}`, expected a name like `blue` or a hex value like `#5f87af`",
+            value
+        ))
+    })
+}
@@ -0,0 +1,844 @@
+use std::{collections::HashMap, fmt};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::buffer::{display_width, EditKind, TextBuffer, UndoStack};
+
+const UNNAMED_REGISTER: char = '"';
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Normal => write!(f, "NORMAL"),
+            Mode::Insert => write!(f, "INSERT"),
+            Mode::Visual => write!(f, "VISUAL"),
+        }
+    }
+}
+
+// What the editor should do after vim handled a key
+pub enum Outcome {
+    Handled,
+    // replay these keys, used by `.`
+    Repeat(Vec<KeyEvent>, usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Motion {
+    Left,
+    Right,
+    Down,
+    Up,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FirstLine,
+    LastLine,
+}
+
+// How the text between the cursor and a motion's target is selected by an operator
+#[derive(Clone, Copy, PartialEq)]
+enum Span {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+enum Command {
+    Move(Motion),
+    Operate(Operator, Option<Motion>),
+    DeleteChar,
+    DeleteCharBefore,
+    Put,
+    PutBefore,
+    Insert,
+    Append,
+    InsertLineStart,
+    AppendLineEnd,
+    OpenBelow,
+    OpenAbove,
+    Visual,
+    Undo,
+    Redo,
+    Repeat,
+}
+
+enum Parse {
+    Incomplete,
+    Invalid,
+    Complete {
+        register: Option<char>,
+        count: Option<usize>,
+        command: Command,
+    },
+}
+
+struct Register {
+    text: String,
+    linewise: bool,
+}
+
+// Modal editing state for the prompt editor
+pub struct Vim {
+    mode: Mode,
+    // keys of the command being typed in normal or visual mode
+    pending: Vec<KeyEvent>,
+    registers: HashMap<char, Register>,
+    // keys of the change being recorded, kept open while inserting
+    recording: Option<Vec<KeyEvent>>,
+    last_change: Vec<KeyEvent>,
+    replaying: bool,
+    visual_anchor: usize,
+}
+
+impl Default for Vim {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            registers: HashMap::new(),
+            recording: None,
+            last_change: Vec::new(),
+            replaying: false,
+            visual_anchor: 0,
+        }
+    }
+}
+
+impl Vim {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // the visually selected range, which includes the character under the cursor
+    pub fn selection(&self, buffer: &TextBuffer) -> Option<std::ops::Range<usize>> {
+        if self.mode != Mode::Visual {
+            return None;
+        }
+        let start = self.visual_anchor.min(buffer.cursor());
+        let end = self.visual_anchor.max(buffer.cursor());
+        Some(start..buffer.next_boundary(end).max(end))
+    }
+
+    // keys typed in insert mode are part of the change `.` repeats
+    pub fn record(&mut self, input: KeyEvent) {
+        if let Some(recording) = &mut self.recording {
+            recording.push(input);
+        }
+    }
+
+    pub fn leave_insert(&mut self, input: KeyEvent, buffer: &mut TextBuffer) {
+        self.record(input);
+        if let Some(recording) = self.recording.take() {
+            if !self.replaying {
+                self.last_change = recording;
+            }
+        }
+        self.mode = Mode::Normal;
+        let pos = buffer.cursor();
+        if pos > buffer.line_start(pos) {
+            buffer.move_to(buffer.prev_boundary(pos), false);
+        }
+    }
+
+    // text was inserted from outside the editor, e.g. a template replaced the visual selection
+    pub fn leave_visual(&mut self) {
+        if self.mode == Mode::Visual {
+            self.mode = Mode::Normal;
+        }
+        self.pending.clear();
+    }
+
+    pub fn finish_repeat(&mut self) {
+        self.replaying = false;
+    }
+
+    // handle a key in normal or visual mode
+    pub fn process_key(
+        &mut self,
+        input: KeyEvent,
+        buffer: &mut TextBuffer,
+        undo: &mut UndoStack,
+    ) -> Outcome {
+        if input.code == KeyCode::Esc {
+            self.pending.clear();
+            if self.mode == Mode::Visual {
+                self.mode = Mode::Normal;
+            }
+            return Outcome::Handled;
+        }
+
+        self.pending.push(input);
+        let (register, count, command) = match parse(&self.pending, self.mode == Mode::Visual) {
+            Parse::Incomplete => return Outcome::Handled,
+            Parse::Invalid => {
+                self.pending.clear();
+                return Outcome::Handled;
+            }
+            Parse::Complete {
+                register,
+                count,
+                command,
+            } => (register, count, command),
+        };
+        let keys = std::mem::take(&mut self.pending);
+
+        if let Command::Repeat = command {
+            if self.last_change.is_empty() {
+                return Outcome::Handled;
+            }
+            self.replaying = true;
+            return Outcome::Repeat(self.last_change.clone(), count.unwrap_or(1));
+        }
+
+        let register = register.unwrap_or(UNNAMED_REGISTER);
+        let changes = if self.mode == Mode::Visual {
+            self.execute_visual(command, register, count, buffer, undo)
+        } else {
+            self.execute(command, register, count, buffer, undo)
+        };
+
+        if changes && !self.replaying {
+            if self.mode == Mode::Insert {
+                self.recording = Some(keys);
+            } else {
+                self.last_change = keys;
+            }
+        } else if changes && self.mode == Mode::Insert {
+            self.recording = Some(Vec::new());
+        }
+
+        if self.mode != Mode::Insert {
+            clamp_cursor(buffer);
+        }
+        Outcome::Handled
+    }
+
+    // run a normal mode command, returns true if it is a change `.` can repeat
+    fn execute(
+        &mut self,
+        command: Command,
+        register: char,
+        count: Option<usize>,
+        buffer: &mut TextBuffer,
+        undo: &mut UndoStack,
+    ) -> bool {
+        let n = count.unwrap_or(1);
+        let pos = buffer.cursor();
+        undo.seal();
+
+        match command {
+            Command::Move(motion) => {
+                let (target, _) = motion_target(buffer, motion, count, false);
+                buffer.move_to(target, false);
+                false
+            }
+            Command::Operate(op, motion) => {
+                let (start, end, linewise) = match motion {
+                    // doubled operators like `dd` work on whole lines
+                    None => {
+                        let last = nth_line_start(buffer, line_index(buffer, pos) + n - 1);
+                        (buffer.line_start(pos), buffer.line_end(last), true)
+                    }
+                    Some(motion) => operator_range(buffer, op, motion, count),
+                };
+                self.operate(op, register, start, end, linewise, buffer, undo);
+                op != Operator::Yank
+            }
+            Command::DeleteChar => {
+                let end = (0..n).fold(pos, |p, _| {
+                    buffer.next_boundary(p).min(buffer.line_end(pos))
+                });
+                self.operate(Operator::Delete, register, pos, end, false, buffer, undo);
+                true
+            }
+            Command::DeleteCharBefore => {
+                let start = (0..n).fold(pos, |p, _| {
+                    buffer.prev_boundary(p).max(buffer.line_start(pos))
+                });
+                self.operate(Operator::Delete, register, start, pos, false, buffer, undo);
+                true
+            }
+            Command::Put | Command::PutBefore => {
+                let Some(reg) = self.registers.get(&register) else {
+                    return false;
+                };
+                let text = reg.text.repeat(n);
+                let after = matches!(command, Command::Put);
+                undo.edit(buffer, EditKind::Other, |buffer| {
+                    if reg.linewise {
+                        let body = text.strip_suffix('\n').unwrap_or(&text);
+                        let at = match after {
+                            true => buffer.line_end(pos),
+                            false => buffer.line_start(pos),
+                        };
+                        let inserted = match after {
+                            true => format!("\n{}", body),
+                            false => format!("{}\n", body),
+                        };
+                        buffer.replace_range(at..at, &inserted);
+                        let line = if after { at + 1 } else { at };
+                        buffer.move_to(line, false);
+                    } else {
+                        let at = match after && pos < buffer.line_end(pos) {
+                            true => buffer.next_boundary(pos),
+                            false => pos,
+                        };
+                        buffer.replace_range(at..at, &text);
+                        let end = buffer.cursor();
+                        buffer.move_to(buffer.prev_boundary(end), false);
+                    }
+                });
+                true
+            }
+            Command::Insert => {
+                self.mode = Mode::Insert;
+                true
+            }
+            Command::Append => {
+                if pos < buffer.line_end(pos) {
+                    buffer.move_to(buffer.next_boundary(pos), false);
+                }
+                self.mode = Mode::Insert;
+                true
+            }
+            Command::InsertLineStart => {
+                buffer.move_to(first_non_blank(buffer, pos), false);
+                self.mode = Mode::Insert;
+                true
+            }
+            Command::AppendLineEnd => {
+                buffer.move_to(buffer.line_end(pos), false);
+                self.mode = Mode::Insert;
+                true
+            }
+            Command::OpenBelow | Command::OpenAbove => {
+                let below = matches!(command, Command::OpenBelow);
+                undo.edit(buffer, EditKind::Other, |buffer| {
+                    if below {
+                        let at = buffer.line_end(pos);
+                        buffer.replace_range(at..at, "\n");
+                    } else {
+                        let at = buffer.line_start(pos);
+                        buffer.replace_range(at..at, "\n");
+                        buffer.move_to(at, false);
+                    }
+                });
+                self.mode = Mode::Insert;
+                true
+            }
+            Command::Visual => {
+                self.visual_anchor = pos;
+                self.mode = Mode::Visual;
+                false
+            }
+            Command::Undo => {
+                (0..n).for_each(|_| undo.undo(buffer));
+                false
+            }
+            Command::Redo => {
+                (0..n).for_each(|_| undo.redo(buffer));
+                false
+            }
+            Command::Repeat => false,
+        }
+    }
+
+    // run a command in visual mode, operators act on the selection
+    fn execute_visual(
+        &mut self,
+        command: Command,
+        register: char,
+        count: Option<usize>,
+        buffer: &mut TextBuffer,
+        undo: &mut UndoStack,
+    ) -> bool {
+        let op = match command {
+            Command::Move(motion) => {
+                let (target, _) = motion_target(buffer, motion, count, false);
+                buffer.move_to(target, false);
+                return false;
+            }
+            Command::Visual => {
+                self.mode = Mode::Normal;
+                return false;
+            }
+            Command::Operate(op, _) => op,
+            Command::DeleteChar => Operator::Delete,
+            _ => return false,
+        };
+
+        let Some(range) = self.selection(buffer) else {
+            return false;
+        };
+        self.mode = Mode::Normal;
+        self.operate(op, register, range.start, range.end, false, buffer, undo);
+        op != Operator::Yank
+    }
+
+    // apply an operator to the text between `start` and `end`
+    #[allow(clippy::too_many_arguments)]
+    fn operate(
+        &mut self,
+        op: Operator,
+        register: char,
+        start: usize,
+        end: usize,
+        linewise: bool,
+        buffer: &mut TextBuffer,
+        undo: &mut UndoStack,
+    ) {
+        let mut text = buffer.text()[start..end].to_owned();
+        if linewise {
+            text.push('\n');
+        }
+        self.store(register, text, linewise);
+
+        match op {
+            Operator::Yank => buffer.move_to(start, false),
+            Operator::Delete => undo.edit(buffer, EditKind::Other, |buffer| {
+                // take the newline of whole lines along with them
+                let range = match linewise {
+                    true if end < buffer.text().len() => start..end + 1,
+                    true if start > 0 => start - 1..end,
+                    _ => start..end,
+                };
+                buffer.replace_range(range, "");
+                if linewise {
+                    let pos = buffer.cursor().min(buffer.text().len());
+                    buffer.move_to(buffer.line_start(pos), false);
+                }
+            }),
+            Operator::Change => {
+                undo.edit(buffer, EditKind::Other, |buffer| {
+                    buffer.replace_range(start..end, "");
+                });
+                self.mode = Mode::Insert;
+            }
+        }
+    }
+
+    fn store(&mut self, register: char, text: String, linewise: bool) {
+        if register != UNNAMED_REGISTER {
+            self.registers.insert(
+                register,
+                Register {
+                    text: text.clone(),
+                    linewise,
+                },
+            );
+        }
+        self.registers
+            .insert(UNNAMED_REGISTER, Register { text, linewise });
+    }
+}
+
+// parse a normal mode command, in visual mode operators apply to the selection right away
+fn parse(keys: &[KeyEvent], visual: bool) -> Parse {
+    let mut chars = Vec::new();
+    for key in keys {
+        match key.code {
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                chars.push('\u{12}')
+            }
+            KeyCode::Char(_)
+                if key
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                return Parse::Invalid
+            }
+            KeyCode::Char(c) => chars.push(c),
+            KeyCode::Left => chars.push('h'),
+            KeyCode::Right => chars.push('l'),
+            KeyCode::Down => chars.push('j'),
+            KeyCode::Up => chars.push('k'),
+            KeyCode::Home => chars.push('0'),
+            KeyCode::End => chars.push('$'),
+            _ => return Parse::Invalid,
+        }
+    }
+
+    let mut idx = 0;
+    let register = match chars.first() {
+        Some('"') => match chars.get(1) {
+            Some(&c) if c.is_ascii_alphanumeric() || c == '"' => {
+                idx = 2;
+                Some(c)
+            }
+            Some(_) => return Parse::Invalid,
+            None => return Parse::Incomplete,
+        },
+        _ => None,
+    };
+
+    let first_count = parse_count(&chars, &mut idx);
+    let Some(&c) = chars.get(idx) else {
+        return Parse::Incomplete;
+    };
+    idx += 1;
+
+    let command = match c {
+        'd' | 'c' | 'y' => {
+            let op = match c {
+                'd' => Operator::Delete,
+                'c' => Operator::Change,
+                _ => Operator::Yank,
+            };
+            if visual {
+                return Parse::Complete {
+                    register,
+                    count: first_count,
+                    command: Command::Operate(op, None),
+                };
+            }
+            let second_count = parse_count(&chars, &mut idx);
+            let count = match (first_count, second_count) {
+                (None, None) => None,
+                (a, b) => Some(a.unwrap_or(1) * b.unwrap_or(1)),
+            };
+            return match chars.get(idx) {
+                None => Parse::Incomplete,
+                Some(&m) if m == c => Parse::Complete {
+                    register,
+                    count,
+                    command: Command::Operate(op, None),
+                },
+                Some(_) => match parse_motion(&chars[idx..]) {
+                    Parse::Complete {
+                        command: Command::Move(motion),
+                        ..
+                    } => Parse::Complete {
+                        register,
+                        count,
+                        command: Command::Operate(op, Some(motion)),
+                    },
+                    Parse::Incomplete => Parse::Incomplete,
+                    _ => Parse::Invalid,
+                },
+            };
+        }
+        'D' => Command::Operate(Operator::Delete, Some(Motion::LineEnd)),
+        'C' => Command::Operate(Operator::Change, Some(Motion::LineEnd)),
+        'x' => Command::DeleteChar,
+        'X' => Command::DeleteCharBefore,
+        'p' => Command::Put,
+        'P' => Command::PutBefore,
+        'i' => Command::Insert,
+        'a' => Command::Append,
+        'I' => Command::InsertLineStart,
+        'A' => Command::AppendLineEnd,
+        'o' => Command::OpenBelow,
+        'O' => Command::OpenAbove,
+        'v' => Command::Visual,
+        'u' => Command::Undo,
+        '\u{12}' => Command::Redo,
+        '.' => Command::Repeat,
+        _ => {
+            return match parse_motion(&chars[idx - 1..]) {
+                Parse::Complete { command, .. } => Parse::Complete {
+                    register,
+                    count: first_count,
+                    command,
+                },
+                other => other,
+            }
+        }
+    };
+
+    match idx == chars.len() {
+        true => Parse::Complete {
+            register,
+            count: first_count,
+            command,
+        },
+        false => Parse::Invalid,
+    }
+}
+
+fn parse_motion(chars: &[char]) -> Parse {
+    let motion = match chars {
+        ['h'] => Motion::Left,
+        ['l'] | [' '] => Motion::Right,
+        ['j'] => Motion::Down,
+        ['k'] => Motion::Up,
+        ['w'] => Motion::WordForward,
+        ['b'] => Motion::WordBackward,
+        ['e'] => Motion::WordEnd,
+        ['0'] => Motion::LineStart,
+        ['^'] => Motion::FirstNonBlank,
+        ['$'] => Motion::LineEnd,
+        ['G'] => Motion::LastLine,
+        ['g'] => return Parse::Incomplete,
+        ['g', 'g'] => Motion::FirstLine,
+        _ => return Parse::Invalid,
+    };
+    Parse::Complete {
+        register: None,
+        count: None,
+        command: Command::Move(motion),
+    }
+}
+
+// a count never starts with 0, which is the line start motion
+fn parse_count(chars: &[char], idx: &mut usize) -> Option<usize> {
+    let start = *idx;
+    while let Some(c) = chars.get(*idx) {
+        if c.is_ascii_digit() && !(*idx == start && *c == '0') {
+            *idx += 1;
+        } else {
+            break;
+        }
+    }
+    let digits: String = chars[start..*idx].iter().collect();
+    digits.parse().ok()
+}
+
+// Where a motion moves the cursor, and how an operator would select up to it
+fn motion_target(
+    buffer: &TextBuffer,
+    motion: Motion,
+    count: Option<usize>,
+    for_operator: bool,
+) -> (usize, Span) {
+    let n = count.unwrap_or(1);
+    let pos = buffer.cursor();
+    let line = line_index(buffer, pos);
+
+    match motion {
+        Motion::Left => {
+            let start = buffer.line_start(pos);
+            let target = (0..n).fold(pos, |p, _| buffer.prev_boundary(p).max(start));
+            (target, Span::Exclusive)
+        }
+        Motion::Right => {
+            let end = buffer.line_end(pos);
+            // in normal mode the cursor rests on the last character, operators may reach the end
+            let last = match for_operator {
+                true => end,
+                false => last_char(buffer, pos),
+            };
+            let target = (0..n).fold(pos, |p, _| buffer.next_boundary(p).min(last));
+            (target, Span::Exclusive)
+        }
+        Motion::Down | Motion::Up => {
+            let target_line = match motion {
+                Motion::Down => line + n,
+                _ => line.saturating_sub(n),
+            };
+            (same_column(buffer, pos, target_line), Span::Linewise)
+        }
+        Motion::WordForward => {
+            let target = (0..n).fold(pos, |p, _| buffer.word_start_after(p));
+            (target, Span::Exclusive)
+        }
+        Motion::WordBackward => {
+            let target = (0..n).fold(pos, |p, _| buffer.word_start_before(p));
+            (target, Span::Exclusive)
+        }
+        Motion::WordEnd => {
+            let target = (0..n).fold(pos, |p, _| word_end(buffer, p));
+            (target, Span::Inclusive)
+        }
+        Motion::LineStart => (buffer.line_start(pos), Span::Exclusive),
+        Motion::FirstNonBlank => (first_non_blank(buffer, pos), Span::Exclusive),
+        Motion::LineEnd => {
+            let last = nth_line_start(buffer, line + n - 1);
+            match for_operator {
+                true => (buffer.line_end(last), Span::Exclusive),
+                false => (last_char(buffer, last), Span::Exclusive),
+            }
+        }
+        Motion::FirstLine | Motion::LastLine => {
+            let lines = buffer.text().split('\n').count();
+            let target_line = match (motion, count) {
+                (_, Some(n)) => n - 1,
+                (Motion::FirstLine, None) => 0,
+                _ => lines - 1,
+            };
+            let start = nth_line_start(buffer, target_line);
+            (first_non_blank(buffer, start), Span::Linewise)
+        }
+    }
+}
+
+// the range an operator acts on and whether it covers whole lines
+fn operator_range(
+    buffer: &TextBuffer,
+    op: Operator,
+    motion: Motion,
+    count: Option<usize>,
+) -> (usize, usize, bool) {
+    let pos = buffer.cursor();
+    // `cw` changes to the end of the word like `ce`
+    let on_word = pos < buffer.text().len()
+        && !buffer.text()[pos..buffer.next_boundary(pos)]
+            .chars()
+            .all(char::is_whitespace);
+    let motion = match (op, motion) {
+        (Operator::Change, Motion::WordForward) if on_word => Motion::WordEnd,
+        _ => motion,
+    };
+
+    let (target, span) = motion_target(buffer, motion, count, true);
+    let (start, end) = (pos.min(target), pos.max(target));
+    match span {
+        Span::Linewise => (buffer.line_start(start), buffer.line_end(end), true),
+        Span::Inclusive => (start, buffer.next_boundary(end).max(end), false),
+        // a word motion from the last word of a line stops at the end of that line
+        Span::Exclusive if motion == Motion::WordForward => {
+            (start, end.min(buffer.line_end(start)).max(start), false)
+        }
+        Span::Exclusive => (start, end, false),
+    }
+}
+
+fn line_index(buffer: &TextBuffer, pos: usize) -> usize {
+    buffer.text()[..pos].matches('\n').count()
+}
+
+// start of the given line, or of the last line if there aren't that many
+fn nth_line_start(buffer: &TextBuffer, line: usize) -> usize {
+    let mut start = 0;
+    for _ in 0..line {
+        match buffer.text()[start..].find('\n') {
+            Some(idx) => start += idx + 1,
+            None => break,
+        }
+    }
+    start
+}
+
+fn first_non_blank(buffer: &TextBuffer, pos: usize) -> usize {
+    let start = buffer.line_start(pos);
+    let line = &buffer.text()[start..buffer.line_end(pos)];
+    start + line.len() - line.trim_start().len()
+}
+
+// the last character of the line, where the normal mode cursor stops
+fn last_char(buffer: &TextBuffer, pos: usize) -> usize {
+    let (start, end) = (buffer.line_start(pos), buffer.line_end(pos));
+    match end > start {
+        true => buffer.prev_boundary(end),
+        false => end,
+    }
+}
+
+// the last character of the word at or after `pos`
+fn word_end(buffer: &TextBuffer, pos: usize) -> usize {
+    let text = buffer.text();
+    let next = buffer.next_boundary(pos);
+    let mut last = next;
+    let mut started = false;
+    for (idx, grapheme) in text[next..].grapheme_indices(true) {
+        let whitespace = grapheme.chars().all(char::is_whitespace);
+        if whitespace && started {
+            break;
+        }
+        if !whitespace {
+            if started && class(grapheme) != class(&text[last..buffer.next_boundary(last)]) {
+                break;
+            }
+            started = true;
+            last = next + idx;
+        }
+    }
+    match started {
+        true => last,
+        false => pos,
+    }
+}
+
+fn class(grapheme: &str) -> u8 {
+    match grapheme.chars().next() {
+        Some(c) if c.is_alphanumeric() || c == '_' => 1,
+        _ => 2,
+    }
+}
+
+// the offset in `line` at the same display column as `pos`
+fn same_column(buffer: &TextBuffer, pos: usize, line: usize) -> usize {
+    let column = display_width(&buffer.text()[buffer.line_start(pos)..pos]);
+    let start = nth_line_start(buffer, line);
+    let end = buffer.line_end(start);
+    let mut width = 0;
+    for (idx, grapheme) in buffer.text()[start..end].grapheme_indices(true) {
+        width += display_width(grapheme);
+        if width > column {
+            return start + idx;
+        }
+    }
+    last_char(buffer, start)
+}
+
+// the normal mode cursor sits on a character, never after the end of a line
+fn clamp_cursor(buffer: &mut TextBuffer) {
+    let pos = buffer.cursor();
+    let last = last_char(buffer, pos);
+    if pos > last {
+        buffer.move_to(last, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(text: &str, cursor: usize, keys: &str) -> (String, usize) {
+        let mut buffer = TextBuffer::new(text);
+        buffer.move_to(cursor, false);
+        let mut undo = UndoStack::default();
+        let mut vim = Vim::default();
+        for c in keys.chars() {
+            let key = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+            match vim.mode() {
+                Mode::Insert => buffer.insert_str(&c.to_string()),
+                _ => {
+                    vim.process_key(key, &mut buffer, &mut undo);
+                }
+            }
+        }
+        (buffer.text().to_owned(), buffer.cursor())
+    }
+
+    #[test]
+    fn test_operators_and_counts() {
+        assert_eq!(run("one two three", 0, "dw").0, "two three");
+        assert_eq!(run("one two three", 0, "d2w").0, "three");
+        assert_eq!(run("one two three", 0, "2dw").0, "three");
+        assert_eq!(run("one two three", 0, "cwuno").0, "uno two three");
+        assert_eq!(run("one two three", 4, "d$").0, "one ");
+        assert_eq!(run("a\nb\nc\nd", 2, "2dd").0, "a\nd");
+        assert_eq!(run("a\nb\nc", 0, "yyjp").0, "a\nb\na\nc");
+        assert_eq!(run("a\nb\nc", 0, "\"qyyGdd\"qP").0, "a\na\nb");
+        assert_eq!(run("one two", 0, "vlld").0, " two");
+        assert_eq!(run("hello", 0, "3x").0, "lo");
+    }
+
+    #[test]
+    fn test_motions() {
+        assert_eq!(run("one two", 0, "$").1, 6);
+        assert_eq!(run("one two", 6, "0").1, 0);
+        assert_eq!(run("a\nb\nc", 0, "G").1, 4);
+        assert_eq!(run("a\nb\nc", 4, "gg").1, 0);
+        assert_eq!(run("one two", 0, "e").1, 2);
+    }
+}
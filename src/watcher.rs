@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use anyhow::Result;
+use crossbeam::channel::Receiver;
+
+// Watches the directories shown in the file browser and reports the ones whose entries
+// changed, e.g. a file was created, deleted or renamed. Subdirectories aren't watched
+// automatically, every directory added to the tree has to be passed to `watch`
+pub struct Watcher {
+    inner: sys::Inotify,
+    // the watched directory behind each watch descriptor
+    dirs: Arc<Mutex<HashMap<i32, PathBuf>>>,
+}
+
+impl Watcher {
+    // start watching in a background thread, each message holds the directories that
+    // changed in one batch of events
+    pub fn start() -> Result<(Self, Receiver<Vec<PathBuf>>)> {
+        let inner = sys::Inotify::new()?;
+        let dirs = Arc::new(Mutex::new(HashMap::new()));
+        let changes = inner.spawn_reader(dirs.clone());
+        Ok((Self { inner, dirs }, changes))
+    }
+
+    // watching a directory twice is harmless, the kernel hands out the same descriptor
+    pub fn watch(&self, dir: &Path) -> Result<()> {
+        let wd = self.inner.add_watch(dir)?;
+        self.dirs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(wd, dir.to_path_buf());
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::{
+        collections::{BTreeSet, HashMap},
+        ffi::CString,
+        fs::File,
+        io::{self, Read},
+        mem,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::ffi::OsStrExt,
+        },
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex, PoisonError},
+        thread,
+    };
+
+    use anyhow::{Context, Result};
+    use crossbeam::channel::{unbounded, Receiver};
+
+    // changes to a directory's entries, modifying a file doesn't change the tree
+    const MASK: u32 = libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_ONLYDIR;
+
+    pub struct Inotify(Arc<OwnedFd>);
+
+    impl Inotify {
+        pub fn new() -> Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error()).context("Failed to start inotify");
+            }
+            Ok(Self(Arc::new(unsafe { OwnedFd::from_raw_fd(fd) })))
+        }
+
+        pub fn add_watch(&self, dir: &Path) -> Result<i32> {
+            let path = CString::new(dir.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.0.as_raw_fd(), path.as_ptr(), MASK) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error())
+                    .context(format!("Failed to watch {}", dir.display()));
+            }
+            Ok(wd)
+        }
+
+        pub fn spawn_reader(
+            &self,
+            dirs: Arc<Mutex<HashMap<i32, PathBuf>>>,
+        ) -> Receiver<Vec<PathBuf>> {
+            let (tx, rx) = unbounded();
+            let mut file = File::from(self.0.try_clone().expect("inotify fd can be duplicated"));
+            thread::spawn(move || {
+                let mut buffer = vec![0u8; 64 * 1024];
+                // a read returns every event that is queued, which batches bursts of changes
+                while let Ok(len) = file.read(&mut buffer) {
+                    let mut changed = BTreeSet::new();
+                    let mut dirs = dirs.lock().unwrap_or_else(PoisonError::into_inner);
+                    let mut offset = 0;
+                    while offset + mem::size_of::<libc::inotify_event>() <= len {
+                        let event: libc::inotify_event =
+                            unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+                        offset += mem::size_of::<libc::inotify_event>() + event.len as usize;
+                        if event.mask & libc::IN_IGNORED != 0 {
+                            // the directory is gone, its parent reports the deletion
+                            dirs.remove(&event.wd);
+                        } else if let Some(dir) = dirs.get(&event.wd) {
+                            changed.insert(dir.clone());
+                        }
+                    }
+                    drop(dirs);
+                    if !changed.is_empty() && tx.send(changed.into_iter().collect()).is_err() {
+                        break;
+                    }
+                }
+            });
+            rx
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::{bail, Result};
+    use crossbeam::channel::{self, Receiver};
+
+    const UNSUPPORTED: &str = "Watching the project for changes is only supported on Linux";
+
+    pub struct Inotify;
+
+    impl Inotify {
+        pub fn new() -> Result<Self> {
+            bail!(UNSUPPORTED)
+        }
+
+        pub fn add_watch(&self, _dir: &Path) -> Result<i32> {
+            bail!(UNSUPPORTED)
+        }
+
+        pub fn spawn_reader(
+            &self,
+            _dirs: Arc<Mutex<HashMap<i32, PathBuf>>>,
+        ) -> Receiver<Vec<PathBuf>> {
+            // nothing is watched, so nothing ever changes
+            channel::never()
+        }
+    }
+}
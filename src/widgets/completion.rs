@@ -0,0 +1,47 @@
+use std::ops::Range;
+
+use ratatui::widgets::ListState;
+
+// One entry of the completion popup
+pub struct Candidate {
+    pub label: String,
+    // text that replaces the completed range when accepted
+    pub replacement: String,
+    // char indices of `label` to highlight
+    pub positions: Vec<usize>,
+}
+
+// Popup listing completions for the text being typed in the prompt editor
+pub struct State {
+    pub candidates: Vec<Candidate>,
+    pub list_state: ListState,
+    // byte range of the prompt text the accepted candidate replaces
+    pub range: Range<usize>,
+}
+
+impl State {
+    pub fn new(range: Range<usize>, candidates: Vec<Candidate>) -> Self {
+        let mut list_state = ListState::default();
+        list_state.select(Some(0));
+        Self {
+            candidates,
+            list_state,
+            range,
+        }
+    }
+
+    pub fn selected(&self) -> Option<&Candidate> {
+        self.candidates.get(self.list_state.selected()?)
+    }
+
+    pub fn select_previous(&mut self) {
+        let selected = self.list_state.selected().unwrap_or(0);
+        self.list_state.select(Some(selected.saturating_sub(1)));
+    }
+
+    pub fn select_next(&mut self) {
+        let selected = self.list_state.selected().unwrap_or(0);
+        let last = self.candidates.len().saturating_sub(1);
+        self.list_state.select(Some((selected + 1).min(last)));
+    }
+}
@@ -1,41 +1,232 @@
-use crossterm::event::{KeyCode, KeyEvent};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    mem,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+};
+
+use crossbeam::channel::{Receiver, TryRecvError};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::widgets::ListState;
 
-use crate::input::InputHandler;
+use crate::{
+    fuzzy,
+    input::{InputHandler, Keymap},
+    scanner::{self, Batch, Filters},
+    widgets::completion::Candidate,
+};
+
+const MAX_COMPLETIONS: usize = 10;
 
 pub struct State {
+    // files are included and excluded through set_excluded, or update_counts after
     pub file_list: Vec<TreeEntry>,
     pub list_state: ListState,
+    // files toggled or jumped to this session, most recent last
+    recent: Vec<usize>,
+    pub search: Option<Search>,
+    // indices of the entries that aren't inside a collapsed directory, kept up to date
+    // as directories are expanded and collapsed instead of being worked out every frame
+    visible: Vec<usize>,
+    // (included, total) files below each directory, updated as files are included and
+    // excluded through set_excluded instead of being counted every frame
+    file_counts: Vec<(usize, usize)>,
+    // the scan of the whole project, running in the background until it's done
+    scan: Option<Receiver<Batch>>,
+    // entries found by the background scan so far
+    pub scanned: usize,
+    // bumped whenever entries are added or removed, indices from before are stale
+    revision: usize,
+    // directories added to the tree since they were last taken, e.g. to watch them
+    added_dirs: Vec<PathBuf>,
+    // whether each directory toggled this session was included, files the scan finds
+    // below it later follow the closest one
+    toggled_dirs: HashMap<PathBuf, bool>,
+    // mentions leave out the root's label when there is only one, the roots are fixed
+    // once the tree is made since rebuilding only ever replaces entries below them
+    single_root: bool,
+    filters: Filters,
+    keys: Keymap,
+}
+
+// a `/` search over the names of every entry, collapsed or not
+pub struct Search {
+    pub query: String,
+    // still typing the query, n and N move between matches once it is confirmed
+    pub typing: bool,
+    // the entry selected before the search, restored when it is cancelled
+    origin: Option<usize>,
+    // the entries whose names match, found again when the query or the tree changes
+    matches: Vec<usize>,
+}
+
+impl Search {
+    // the positions of the characters the query matched in `name`, smartcase like vim
+    pub fn find(&self, name: &str) -> Option<Vec<usize>> {
+        if self.query.is_empty() {
+            return None;
+        }
+        let case_sensitive = self.query.chars().any(char::is_uppercase);
+        let fold = |c: char| match case_sensitive {
+            true => c,
+            false => c.to_lowercase().next().unwrap_or(c),
+        };
+        let name: Vec<char> = name.chars().map(fold).collect();
+        let query: Vec<char> = self.query.chars().map(fold).collect();
+        let start = name
+            .windows(query.len())
+            .position(|window| window == query.as_slice())?;
+        Some((start..start + query.len()).collect())
+    }
 }
 
 impl State {
-    pub fn new(entries: Vec<TreeEntry>) -> Self {
+    pub fn new(entries: Vec<TreeEntry>, keys: Keymap) -> Self {
         let mut list_state = ListState::default();
         list_state.select(Some(0));
-        Self {
+        let single_root = entries.iter().filter(|entry| entry.depth == 0).count() == 1;
+        let mut state = Self {
             file_list: entries,
             list_state,
+            recent: Vec::new(),
+            search: None,
+            visible: Vec::new(),
+            file_counts: Vec::new(),
+            scan: None,
+            scanned: 0,
+            revision: 0,
+            added_dirs: Vec::new(),
+            toggled_dirs: HashMap::new(),
+            single_root,
+            filters: Filters::default(),
+            keys,
+        };
+        state.update_visible();
+        state.update_counts();
+        state
+    }
+
+    // the roots with their children, deeper directories are loaded by the background scan
+    // or when they are expanded, whichever comes first
+    pub fn load(roots: &[PathBuf], filters: Filters, keys: Keymap) -> Self {
+        let mut entries = Vec::new();
+        let mut labels: Vec<String> = Vec::new();
+        for root in roots {
+            let label = scanner::root_label(root, &labels);
+            entries.push(scanner::root_entry(root, &label));
+            entries.extend(scanner::list_children(root, &label, root, &filters));
+            labels.push(label);
         }
+        let mut state = Self::new(entries, keys);
+        state.filters = filters;
+        state.start_scan();
+        state
+    }
+
+    fn start_scan(&mut self) {
+        let roots = self
+            .file_list
+            .iter()
+            .filter(|entry| entry.depth == 0)
+            .map(|root| (PathBuf::from(&root.path), root.display_path.clone()))
+            .collect();
+        self.scan = Some(scanner::scan_in_background(roots, self.filters.clone()));
+        self.scanned = 0;
+    }
+
+    pub fn filters(&self) -> &Filters {
+        &self.filters
+    }
+
+    // list everything that is loaded again with different filters
+    fn set_filters(&mut self, filters: Filters) {
+        self.filters = filters;
+        // scan again for the directories the filters reveal, a scan that is still running
+        // would also add back what's now filtered out
+        self.start_scan();
+        let roots: Vec<String> = self
+            .file_list
+            .iter()
+            .filter(|entry| entry.depth == 0)
+            .map(|root| root.path.clone())
+            .collect();
+        for root_path in roots {
+            let Some(root) = self
+                .file_list
+                .iter()
+                .position(|entry| entry.path == root_path)
+            else {
+                continue;
+            };
+            let (root_dir, label) = self.root_of(root);
+            let listings = std::iter::once(root)
+                .chain(self.subtree(root))
+                .filter(|&idx| {
+                    let entry = &self.file_list[idx];
+                    !entry.deleted
+                        && matches!(
+                            entry.entry_type,
+                            EntryType::Directory(DirectoryState { loaded: true, .. })
+                        )
+                })
+                .map(|idx| {
+                    let dir = PathBuf::from(&self.file_list[idx].path);
+                    let children = scanner::list_children(&root_dir, &label, &dir, &self.filters);
+                    (dir, children)
+                })
+                .collect();
+            self.relist(root, listings);
+        }
+    }
+
+    pub fn scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    // wait for the background scan, e.g. before the tree is sent
+    pub fn finish_scan(&mut self) {
+        if let Some(scan) = self.scan.take() {
+            for batch in scan.iter() {
+                self.add_batch(batch);
+            }
+        }
+    }
+
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    pub fn take_added_dirs(&mut self) -> Vec<PathBuf> {
+        mem::take(&mut self.added_dirs)
+    }
+
+    // the entry under the cursor, without selecting the first one like moving does
+    pub fn selected_idx(&self) -> Option<usize> {
+        let offset = self.list_state.selected()?;
+        self.visible.get(offset).copied()
+    }
+
+    pub fn selected_entry(&self) -> Option<&TreeEntry> {
+        self.file_list.get(self.selected_idx()?)
     }
 
     fn get_selected_idx(&mut self) -> Option<usize> {
-        let visible = self.visible_entries();
         // Find the offset selected in the "visible" slice
         let selected_offset = match self.list_state.selected() {
             Some(offset) => offset,
             None => {
                 self.list_state.select(Some(0));
-                return visible.first().copied();
+                return self.visible.first().copied();
             }
         };
         // Return the real index in the file_list
-        visible.get(selected_offset).copied()
+        self.visible.get(selected_offset).copied()
     }
 
     fn increment_selected(&mut self) {
-        let visible = self.visible_entries();
         if let Some(offset) = self.list_state.selected() {
-            if offset < visible.len().saturating_sub(1) {
+            if offset < self.visible.len().saturating_sub(1) {
                 self.list_state.select(Some(offset + 1));
             }
         } else {
@@ -44,22 +235,30 @@ impl State {
     }
 
     fn decrement_selected(&mut self) {
-        let visible = self.visible_entries();
         if let Some(offset) = self.list_state.selected() {
             if offset > 0 {
                 self.list_state.select(Some(offset - 1));
             }
         } else {
             self.list_state
-                .select(Some(visible.len().saturating_sub(1)));
+                .select(Some(self.visible.len().saturating_sub(1)));
         }
     }
 
-    pub fn visible_entries(&self) -> Vec<usize> {
+    pub fn visible_entries(&self) -> &[usize] {
+        &self.visible
+    }
+
+    fn update_visible(&mut self) {
+        self.visible = self.visible_in(0..self.file_list.len());
+    }
+
+    // the entries in the range that aren't hidden by a collapsed directory in the range
+    fn visible_in(&self, range: Range<usize>) -> Vec<usize> {
         let mut visible = Vec::new();
         let mut collapsed_stack = Vec::new();
 
-        for (idx, entry) in self.file_list.iter().enumerate() {
+        for (idx, entry) in self.file_list[range.clone()].iter().enumerate() {
             // if we're inside a collapsed ancestor, skip
             while let Some(&collapsed_depth) = collapsed_stack.last() {
                 if entry.depth <= collapsed_depth {
@@ -74,7 +273,7 @@ impl State {
             }
 
             // this entry is visible
-            visible.push(idx);
+            visible.push(range.start + idx);
 
             // if this entry is a collapsed directory, note its depth
             if let EntryType::Directory(dir_state) = &entry.entry_type {
@@ -86,19 +285,609 @@ impl State {
         visible
     }
 
-    pub fn get_included_entries(&self) -> Vec<String> {
+    // expand or collapse a directory, only its own part of the visible list changes
+    fn set_expanded(&mut self, idx: usize, expanded: bool) {
+        let EntryType::Directory(dir_state) = &mut self.file_list[idx].entry_type else {
+            return;
+        };
+        if dir_state.expanded == expanded {
+            return;
+        }
+        dir_state.expanded = expanded;
+        // nothing to do while the directory itself is hidden
+        let Ok(offset) = self.visible.binary_search(&idx) else {
+            return;
+        };
+        let children = self.subtree(idx);
+        match expanded {
+            true => {
+                let shown = self.visible_in(children);
+                self.visible.splice(offset + 1..offset + 1, shown);
+            }
+            false => {
+                let end = self
+                    .visible
+                    .partition_point(|&visible| visible < children.end);
+                self.visible.drain(offset + 1..end);
+            }
+        }
+    }
+
+    // expanding a directory the background scan hasn't got to yet lists it right away
+    fn expand(&mut self, idx: usize) {
+        if let EntryType::Directory(DirectoryState { loaded: false, .. }) =
+            self.file_list[idx].entry_type
+        {
+            let (root, label) = self.root_of(idx);
+            let dir = PathBuf::from(&self.file_list[idx].path);
+            let children = scanner::list_children(&root, &label, &dir, &self.filters);
+            self.merge_entries(idx, children, &[]);
+            if let EntryType::Directory(dir_state) = &mut self.file_list[idx].entry_type {
+                dir_state.loaded = true;
+            }
+        }
+        self.set_expanded(idx, true);
+    }
+
+    // the path and label of the root the entry is under
+    fn root_of(&self, idx: usize) -> (PathBuf, String) {
+        let root = self.file_list[..=idx]
+            .iter()
+            .rev()
+            .find(|entry| entry.depth == 0)
+            .unwrap_or(&self.file_list[0]);
+        (PathBuf::from(&root.path), root.display_path.clone())
+    }
+
+    pub fn toggle_file(&mut self, idx: usize) {
+        self.set_excluded(idx, !self.file_list[idx].excluded);
+        self.file_list[idx].line_ranges.clear();
+        self.touch(idx);
+    }
+
+    // include or exclude a file, the counts of the directories above it follow
+    pub fn set_excluded(&mut self, idx: usize, excluded: bool) {
+        if self.file_list[idx].excluded == excluded {
+            return;
+        }
+        self.file_list[idx].excluded = excluded;
+        for ancestor in self.ancestors(idx) {
+            let included = &mut self.file_counts[ancestor].0;
+            *included = match excluded {
+                true => *included - 1,
+                false => *included + 1,
+            };
+        }
+    }
+
+    // include the 1-based lines of a file, or leave them out if they're all included
+    // already, returns whether they're included now
+    pub fn toggle_lines(&mut self, idx: usize, lines: RangeInclusive<usize>) -> bool {
+        let entry = &mut self.file_list[idx];
+        let included = !entry.excluded
+            && entry
+                .line_ranges
+                .iter()
+                .any(|range| range.start() <= lines.start() && lines.end() <= range.end());
+        if !included {
+            if entry.excluded {
+                entry.line_ranges.clear();
+            }
+            // marking lines of a file that is included whole narrows it to them
+            entry.line_ranges.push(lines);
+            merge_ranges(&mut entry.line_ranges);
+            self.set_excluded(idx, false);
+        } else {
+            entry.line_ranges = entry
+                .line_ranges
+                .iter()
+                .flat_map(|range| {
+                    let before = *range.start()..=(*range.end()).min(lines.start() - 1);
+                    let after = (*range.start()).max(lines.end() + 1)..=*range.end();
+                    [before, after]
+                })
+                .filter(|range| !range.is_empty())
+                .collect();
+            let excluded = entry.line_ranges.is_empty();
+            self.set_excluded(idx, excluded);
+        }
+        self.touch(idx);
+        !included
+    }
+
+    // remember the entry was used, the file finder ranks recently used files higher
+    pub fn touch(&mut self, idx: usize) {
+        self.recent.retain(|&recent| recent != idx);
+        self.recent.push(idx);
+    }
+
+    // how many other entries were used since this one, None if it wasn't used this session
+    pub fn recency(&self, idx: usize) -> Option<usize> {
+        self.recent.iter().rev().position(|&recent| recent == idx)
+    }
+
+    // the directories the entry is in, innermost first
+    fn ancestors(&self, idx: usize) -> Vec<usize> {
+        let mut ancestors = Vec::new();
+        let mut depth = self.file_list[idx].depth;
+        for (ancestor, entry) in self.file_list[..idx].iter().enumerate().rev() {
+            if depth == 0 {
+                break;
+            }
+            if entry.depth < depth {
+                depth = entry.depth;
+                ancestors.push(ancestor);
+            }
+        }
+        ancestors
+    }
+
+    // expand the entry's ancestors and move the selection to it
+    pub fn reveal(&mut self, idx: usize) {
+        // outermost first, so each one is visible by the time it is expanded
+        for ancestor in self.ancestors(idx).into_iter().rev() {
+            self.set_expanded(ancestor, true);
+        }
+        let offset = self.visible.binary_search(&idx).ok();
+        self.list_state.select(offset);
+    }
+
+    // the entries matching the current search
+    pub fn search_matches(&self) -> &[usize] {
+        self.search
+            .as_ref()
+            .map_or(&[], |search| search.matches.as_slice())
+    }
+
+    fn update_search_matches(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let matches = self
+            .file_list
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| search.find(entry.name()).is_some())
+            .map(|(idx, _)| idx)
+            .collect();
+        if let Some(search) = &mut self.search {
+            search.matches = matches;
+        }
+    }
+
+    // select the next match after `from`, or before it when going backwards, wrapping around
+    fn jump_to_match(&mut self, from: Option<usize>, forward: bool) -> bool {
+        let matches = self.search_matches();
+        let found = match (from, forward) {
+            (None, true) => matches.first(),
+            (None, false) => matches.last(),
+            (Some(from), true) => matches.iter().find(|&&idx| idx > from).or(matches.first()),
+            (Some(from), false) => matches
+                .iter()
+                .rev()
+                .find(|&&idx| idx < from)
+                .or(matches.last()),
+        };
+        match found.copied() {
+            Some(idx) => {
+                self.reveal(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // keys typed while the search query is being entered
+    fn process_search_key(&mut self, input: KeyEvent) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let origin = search.origin;
+        match input.code {
+            KeyCode::Esc => {
+                self.search = None;
+                if let Some(idx) = origin {
+                    self.reveal(idx);
+                }
+                return;
+            }
+            KeyCode::Enter => {
+                search.typing = false;
+                if search.query.is_empty() {
+                    self.search = None;
+                }
+                return;
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+            }
+            KeyCode::Char(c)
+                if !input
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                search.query.push(c);
+            }
+            _ => return,
+        }
+        self.update_search_matches();
+        // the selection follows the first match at or after where the search started
+        let from = origin.and_then(|idx| idx.checked_sub(1));
+        if !self.jump_to_match(from, true) {
+            if let Some(idx) = origin {
+                self.reveal(idx);
+            }
+        }
+    }
+
+    // only files are ever included, directories are just a way to select many at once
+    pub fn get_included_entries(&self) -> Vec<&TreeEntry> {
         self.file_list
             .iter()
-            .filter(|entry| !entry.excluded)
-            .map(|entry| entry.path.clone())
+            .filter(|entry| entry.is_file() && !entry.excluded && !entry.deleted)
+            .collect()
+    }
+
+    // list the watched directories again after their entries changed on disk
+    pub fn refresh_dirs(&mut self, dirs: &[PathBuf]) {
+        for dir in dirs {
+            // directories that aren't loaded yet will be listed as they are now anyway
+            let Some(idx) = self.file_list.iter().position(|entry| {
+                matches!(
+                    entry.entry_type,
+                    EntryType::Directory(DirectoryState { loaded: true, .. })
+                ) && !entry.deleted
+                    && Path::new(&entry.path) == dir
+            }) else {
+                continue;
+            };
+            // a removed directory is picked up by the refresh of its parent
+            if !dir.is_dir() {
+                continue;
+            }
+            let (root, label) = self.root_of(idx);
+            let children = scanner::list_children(&root, &label, dir, &self.filters);
+            self.relist(idx, vec![(dir.clone(), children)]);
+        }
+    }
+
+    // list directories below `under` again, each with its current children. Entries that
+    // are still listed keep their state and everything below them
+    fn relist(&mut self, under: usize, listings: Vec<(PathBuf, Vec<TreeEntry>)>) {
+        let relisted: HashSet<PathBuf> = listings.iter().map(|(dir, _)| dir.clone()).collect();
+        let mut listed: HashMap<String, TreeEntry> = listings
+            .into_iter()
+            .flat_map(|(_, children)| children)
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        self.rebuild_subtree(under, |old| {
+            let mut merged = Vec::new();
+            let mut removed = Vec::new();
+            let mut removed_dirs: HashSet<PathBuf> = HashSet::new();
+            for (idx, mut entry) in old {
+                let parent = Path::new(&entry.path).parent().unwrap_or(Path::new(""));
+                let still_listed = match (removed_dirs.contains(parent), relisted.contains(parent))
+                {
+                    (true, _) => false,
+                    (false, true) => listed.remove(&entry.path).is_some(),
+                    // its directory wasn't listed again
+                    (false, false) => true,
+                };
+                if !still_listed {
+                    if !entry.is_file() {
+                        removed_dirs.insert(PathBuf::from(&entry.path));
+                    }
+                    removed.push((idx, entry));
+                } else if entry.deleted && relisted.contains(parent) {
+                    // it came back, what's below it has to be listed again
+                    entry.deleted = false;
+                    if let EntryType::Directory(dir_state) = &mut entry.entry_type {
+                        dir_state.loaded = false;
+                        dir_state.expanded = false;
+                    }
+                    merged.push((None, entry));
+                } else {
+                    merged.push((Some(idx), entry));
+                }
+            }
+
+            // included files that were deleted stay in the tree marked as such, along with
+            // the directories they were in, so it's clear why they are no longer sent.
+            // Files that are just filtered out now are dropped
+            let kept: Vec<String> = removed
+                .iter()
+                .filter(|(_, entry)| {
+                    entry.is_file() && !entry.excluded && !Path::new(&entry.path).exists()
+                })
+                .map(|(_, entry)| entry.path.clone())
+                .collect();
+            for (idx, mut entry) in removed {
+                if kept
+                    .iter()
+                    .any(|file| Path::new(file).starts_with(&entry.path))
+                {
+                    entry.deleted = true;
+                    merged.push((Some(idx), entry));
+                }
+            }
+            merged.extend(listed.into_values().map(|entry| (None, entry)));
+            merged.sort_by(|(_, a), (_, b)| Path::new(&a.path).cmp(Path::new(&b.path)));
+            merged
+        });
+    }
+
+    fn add_batch(&mut self, batch: Batch) {
+        let root = self
+            .file_list
+            .iter()
+            .position(|entry| entry.depth == 0 && entry.path == batch.root);
+        if let Some(root) = root {
+            self.scanned += batch.entries.len();
+            self.merge_entries(root, batch.entries, &batch.completed);
+        }
+    }
+
+    // add scanned entries below a directory, in path order, skipping the ones already in
+    // the tree and the ones whose directory was removed in the meantime
+    fn merge_entries(&mut self, dir: usize, entries: Vec<TreeEntry>, completed: &[String]) {
+        let completed: HashSet<&String> = completed.iter().collect();
+        let mut dirs = HashSet::from([PathBuf::from(&self.file_list[dir].path)]);
+        let toggled_dirs = self.toggled_dirs.clone();
+        self.rebuild_subtree(dir, |old| {
+            let mut merged = Vec::with_capacity(old.len() + entries.len());
+            let mut old = old.into_iter().peekable();
+            let mut entries = entries.into_iter().peekable();
+            loop {
+                let (idx, mut entry) = match (old.peek(), entries.peek()) {
+                    (None, None) => break,
+                    (Some(_), None) => old.next().map(|(idx, entry)| (Some(idx), entry)),
+                    (None, Some(_)) => entries.next().map(|entry| (None, entry)),
+                    (Some((_, existing)), Some(entry)) => {
+                        match Path::new(&existing.path).cmp(Path::new(&entry.path)) {
+                            Ordering::Less => old.next().map(|(idx, entry)| (Some(idx), entry)),
+                            Ordering::Greater => entries.next().map(|entry| (None, entry)),
+                            Ordering::Equal => {
+                                entries.next();
+                                old.next().map(|(idx, mut entry)| {
+                                    entry.deleted = false;
+                                    (Some(idx), entry)
+                                })
+                            }
+                        }
+                    }
+                }
+                .expect("peeked entry is there");
+
+                if idx.is_none()
+                    && !Path::new(&entry.path)
+                        .parent()
+                        .is_some_and(|parent| dirs.contains(parent))
+                {
+                    continue;
+                }
+                if let EntryType::Directory(dir_state) = &mut entry.entry_type {
+                    dir_state.loaded |= completed.contains(&entry.path);
+                    dirs.insert(PathBuf::from(&entry.path));
+                }
+                if idx.is_none() && entry.is_file() {
+                    let toggled = Path::new(&entry.path)
+                        .ancestors()
+                        .find_map(|dir| toggled_dirs.get(dir));
+                    if let Some(&included) = toggled {
+                        entry.excluded = !included;
+                    }
+                }
+                merged.push((idx, entry));
+            }
+            merged
+        });
+    }
+
+    // replace the entries below a directory with the ones `build` makes from them, each
+    // paired with its old index or None if it's new. Everything that refers to entries by
+    // index is moved along
+    fn rebuild_subtree(
+        &mut self,
+        dir: usize,
+        build: impl FnOnce(Vec<(usize, TreeEntry)>) -> Vec<(Option<usize>, TreeEntry)>,
+    ) {
+        let selected = self.get_selected_idx();
+        let children = self.subtree(dir);
+        let (start, old_len, total) = (children.start, children.len(), self.file_list.len());
+        let old = self
+            .file_list
+            .drain(children)
+            .enumerate()
+            .map(|(offset, entry)| (start + offset, entry))
+            .collect();
+        let merged = build(old);
+
+        let mut remap: Vec<Option<usize>> = (0..total)
+            .map(|idx| match idx {
+                idx if idx < start => Some(idx),
+                idx if idx >= start + old_len => Some(idx - old_len + merged.len()),
+                _ => None,
+            })
+            .collect();
+        for (offset, (old_idx, entry)) in merged.iter().enumerate() {
+            match old_idx {
+                Some(old_idx) => remap[*old_idx] = Some(start + offset),
+                None if !entry.is_file() => self.added_dirs.push(PathBuf::from(&entry.path)),
+                None => {}
+            }
+        }
+        self.file_list
+            .splice(start..start, merged.into_iter().map(|(_, entry)| entry));
+        self.revision += 1;
+
+        self.recent = self.recent.iter().filter_map(|&idx| remap[idx]).collect();
+        if let Some(search) = &mut self.search {
+            search.origin = search.origin.and_then(|idx| remap[idx]);
+        }
+        self.update_visible();
+        self.update_counts();
+        self.update_search_matches();
+        // the cursor stays on the same entry, or moves to the directory if it was removed
+        if let Some(selected) = selected {
+            self.reveal(remap[selected].unwrap_or(dir));
+        }
+    }
+
+    // the indices of the entry's descendants, which directly follow it in file_list
+    fn subtree(&self, idx: usize) -> Range<usize> {
+        let depth = self.file_list[idx].depth;
+        let end = self.file_list[idx + 1..]
+            .iter()
+            .position(|entry| entry.depth <= depth)
+            .map_or(self.file_list.len(), |offset| idx + 1 + offset);
+        idx + 1..end
+    }
+
+    // include every file below the directory, or exclude them all if they already are.
+    // Files that aren't loaded yet follow the choice when the scan adds them
+    fn toggle_directory(&mut self, idx: usize) {
+        let dir = PathBuf::from(&self.file_list[idx].path);
+        let files: Vec<usize> = self
+            .subtree(idx)
+            .filter(|&idx| self.file_list[idx].is_file())
+            .collect();
+        let include = match files.is_empty() {
+            true => !self.toggled_dirs.get(&dir).copied().unwrap_or(false),
+            false => files.iter().any(|&idx| self.file_list[idx].excluded),
+        };
+        self.toggled_dirs
+            .retain(|toggled, _| !toggled.starts_with(&dir));
+        self.toggled_dirs.insert(dir, include);
+        for idx in files {
+            let entry = &mut self.file_list[idx];
+            entry.excluded = !include;
+            entry.line_ranges.clear();
+        }
+        self.update_counts();
+    }
+
+    // how much of the entry is included, directories by the files below them
+    pub fn selection(&self, idx: usize) -> Selection {
+        let entry = &self.file_list[idx];
+        let (included, total) = self.file_counts[idx];
+        match entry.is_file() {
+            true if entry.excluded => Selection::None,
+            true if !entry.line_ranges.is_empty() => Selection::Partial,
+            true => Selection::All,
+            false if included == 0 => Selection::None,
+            false if included == total => Selection::All,
+            false => Selection::Partial,
+        }
+    }
+
+    // count the files below each directory again, after many were included or excluded
+    // without set_excluded
+    pub fn update_counts(&mut self) {
+        let mut counts = vec![(0, 0); self.file_list.len()];
+        let mut open_dirs: Vec<usize> = Vec::new();
+        for (idx, entry) in self.file_list.iter().enumerate() {
+            while open_dirs
+                .last()
+                .is_some_and(|&dir| self.file_list[dir].depth >= entry.depth)
+            {
+                open_dirs.pop();
+            }
+            if entry.is_file() {
+                for &dir in &open_dirs {
+                    counts[dir].0 += usize::from(!entry.excluded);
+                    counts[dir].1 += 1;
+                }
+            } else {
+                open_dirs.push(idx);
+            }
+        }
+        self.file_counts = counts;
+    }
+
+    // mark the file at the given path as included, returns false if it isn't in the tree
+    pub fn include_path(&mut self, path: &Path) -> bool {
+        let Ok(target) = path.canonicalize() else {
+            return false;
+        };
+        // only the roots are canonicalized, an entry's path is its root's joined with the rest
+        let candidates: Vec<String> = self
+            .file_list
+            .iter()
+            .filter(|root| root.depth == 0)
+            .filter_map(|root| {
+                let rel = target
+                    .strip_prefix(Path::new(&root.path).canonicalize().ok()?)
+                    .ok()?;
+                Some(Path::new(&root.path).join(rel).display().to_string())
+            })
+            .collect();
+        let idx = self
+            .file_list
+            .iter()
+            .position(|entry| entry.is_file() && candidates.contains(&entry.path));
+        match idx {
+            Some(idx) => {
+                self.set_excluded(idx, false);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // how a file is written in an @mention, relative to its root unless there are several roots
+    pub fn mention_path<'a>(&self, entry: &'a TreeEntry) -> &'a str {
+        match entry.display_path.split_once('/') {
+            Some((_, rel)) if self.single_root => rel,
+            _ => &entry.display_path,
+        }
+    }
+
+    // the file an @mention refers to
+    pub fn find_mention(&self, path: &str) -> Option<usize> {
+        let files = || {
+            self.file_list
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.is_file())
+        };
+        files()
+            .find(|(_, entry)| entry.display_path == path)
+            .or_else(|| files().find(|(_, entry)| self.mention_path(entry) == path))
+            .map(|(idx, _)| idx)
+    }
+
+    // files matching a partially typed @mention, best first
+    pub fn mention_candidates(&self, query: &str) -> Vec<Candidate> {
+        let mut matches: Vec<(i64, Candidate)> = self
+            .file_list
+            .iter()
+            .filter(|entry| entry.is_file() && !entry.deleted)
+            .filter_map(|entry| {
+                let path = self.mention_path(entry);
+                let found = fuzzy::fuzzy_match(query, path)?;
+                let candidate = Candidate {
+                    label: path.to_owned(),
+                    replacement: format!("@{} ", path),
+                    positions: found.positions,
+                };
+                Some((found.score, candidate))
+            })
+            .collect();
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score).then(a.label.len().cmp(&b.label.len()))
+        });
+        matches.truncate(MAX_COMPLETIONS);
+        matches
+            .into_iter()
+            .map(|(_, candidate)| candidate)
             .collect()
     }
 
     pub fn get_entire_tree(&self) -> String {
         let mut tree = String::new();
-        for entry in &self.file_list {
+        for entry in self.file_list.iter().filter(|entry| !entry.deleted) {
             let indentation = " ".repeat(entry.depth * 2);
-            let filename = entry.path.clone();
+            let filename = entry.display_path.clone();
             let checked_or_not = if entry.excluded { "" } else { "* " };
             tree.push_str(&format!("{}{}{}\n", indentation, checked_or_not, filename));
         }
@@ -108,66 +897,150 @@ impl State {
 
 impl InputHandler for State {
     fn process_key(&mut self, input: KeyEvent) -> Option<crate::input::Action> {
-        match input.code {
-            KeyCode::Char('k') => {
-                self.decrement_selected();
-            }
-            KeyCode::Char('j') => {
-                self.increment_selected();
-            }
-            KeyCode::Char('l') => {
-                let selected_idx = self.get_selected_idx().unwrap_or(0);
-                if let Some(entry) = self.file_list.get_mut(selected_idx) {
-                    if let EntryType::Directory(ref mut dir_state) = entry.entry_type {
-                        dir_state.expanded = true;
-                    }
-                }
+        if self.search.as_ref().is_some_and(|search| search.typing) {
+            self.process_search_key(input);
+            return None;
+        }
+
+        if self.keys.search.matches(&input) {
+            self.search = Some(Search {
+                query: String::new(),
+                typing: true,
+                origin: self.get_selected_idx(),
+                matches: Vec::new(),
+            });
+        } else if self.search.is_some() && self.keys.search_next.matches(&input) {
+            let from = self.get_selected_idx();
+            self.jump_to_match(from, true);
+        } else if self.search.is_some() && self.keys.search_previous.matches(&input) {
+            let from = self.get_selected_idx();
+            self.jump_to_match(from, false);
+        } else if self.search.is_some() && input.code == KeyCode::Esc {
+            self.search = None;
+        } else if self.keys.toggle_hidden.matches(&input) {
+            let mut filters = self.filters.clone();
+            filters.hidden = !filters.hidden;
+            self.set_filters(filters);
+        } else if self.keys.toggle_ignored.matches(&input) {
+            let mut filters = self.filters.clone();
+            filters.ignored = !filters.ignored;
+            self.set_filters(filters);
+        } else if self.keys.up.matches(&input) {
+            self.decrement_selected();
+        } else if self.keys.down.matches(&input) {
+            self.increment_selected();
+        } else if self.keys.right.matches(&input) {
+            if let Some(selected_idx) = self.get_selected_idx() {
+                self.expand(selected_idx);
             }
-            KeyCode::Char('h') => {
-                let selected_idx = self.get_selected_idx().unwrap_or(0);
-                if let Some(entry) = self.file_list.get_mut(selected_idx) {
-                    if let EntryType::Directory(ref mut dir_state) = entry.entry_type {
-                        dir_state.expanded = false;
-                    }
-                }
+        } else if self.keys.left.matches(&input) {
+            if let Some(selected_idx) = self.get_selected_idx() {
+                self.set_expanded(selected_idx, false);
             }
-            KeyCode::Enter => {
-                let selected_idx = self.get_selected_idx().unwrap_or(0);
-                if let Some(entry) = self.file_list.get_mut(selected_idx) {
-                    // TODO: implement proper directory handling
-                    if let EntryType::File = entry.entry_type {
-                        entry.excluded = !entry.excluded;
-                    }
+        } else if self.keys.select.matches(&input) {
+            let selected_idx = self.get_selected_idx().unwrap_or(0);
+            if let Some(entry) = self.file_list.get_mut(selected_idx) {
+                match entry.entry_type {
+                    EntryType::File => self.toggle_file(selected_idx),
+                    EntryType::Directory(_) => self.toggle_directory(selected_idx),
                 }
             }
-            _ => {}
         }
         None
     }
 
-    fn process_tick(&mut self) {}
+    // take in whatever the background scan found since the last tick
+    fn process_tick(&mut self) {
+        while let Some(scan) = &self.scan {
+            match scan.try_recv() {
+                Ok(batch) => self.add_batch(batch),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.scan = None,
+            }
+        }
+    }
+
+    fn process_paste(&mut self, text: &str) -> Option<crate::input::Action> {
+        if let Some(search) = self.search.as_mut().filter(|search| search.typing) {
+            search.query.push_str(text.trim());
+            let from = search.origin.and_then(|idx| idx.checked_sub(1));
+            self.update_search_matches();
+            self.jump_to_match(from, true);
+        }
+        None
+    }
 }
 
 // Each tree entry has its path, depth (for indentation), and whether it's a dir
+// path is used to read the file, display_path is relative to the root it was found under
 pub struct TreeEntry {
     pub path: String,
+    pub display_path: String,
     pub depth: usize,
     pub entry_type: EntryType,
     pub excluded: bool,
+    // 1-based inclusive line ranges to send instead of the whole file
+    pub line_ranges: Vec<RangeInclusive<usize>>,
+    // removed from disk while included, kept so it can be seen why it isn't sent
+    pub deleted: bool,
 }
 
 impl TreeEntry {
-    pub fn new(path: String, depth: usize, is_dir: bool) -> Self {
+    pub fn new(path: String, display_path: String, depth: usize, is_dir: bool) -> Self {
         Self {
             path,
+            display_path,
             depth,
             entry_type: match is_dir {
-                true => EntryType::Directory(DirectoryState { expanded: true }),
+                true => EntryType::Directory(DirectoryState {
+                    expanded: true,
+                    loaded: true,
+                }),
                 false => EntryType::File,
             },
             excluded: true, // exclude by default
+            line_ranges: Vec::new(),
+            deleted: false,
         }
     }
+
+    pub fn is_file(&self) -> bool {
+        matches!(self.entry_type, EntryType::File)
+    }
+
+    // the last component of the display path, roots are shown by their whole label, which
+    // is a full path when two roots have the same directory name
+    pub fn name(&self) -> &str {
+        if self.depth == 0 {
+            return &self.display_path;
+        }
+        self.display_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&self.display_path)
+    }
+}
+
+// Sort line ranges and join the ones that overlap or touch
+pub fn merge_ranges(ranges: &mut Vec<RangeInclusive<usize>>) {
+    ranges.sort_by_key(|range| *range.start());
+    let mut merged: Vec<RangeInclusive<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges.drain(..) {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end() + 1 => {
+                *last = *last.start()..=*range.end().max(last.end());
+            }
+            _ => merged.push(range),
+        }
+    }
+    *ranges = merged;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    None,
+    Partial,
+    All,
 }
 
 pub enum EntryType {
@@ -176,5 +1049,293 @@ pub enum EntryType {
 }
 
 pub struct DirectoryState {
-    expanded: bool,
+    pub expanded: bool,
+    // whether the directory's children are in the tree
+    pub loaded: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    fn entry(display_path: &str, depth: usize, is_dir: bool) -> TreeEntry {
+        TreeEntry::new(
+            display_path.to_owned(),
+            display_path.to_owned(),
+            depth,
+            is_dir,
+        )
+    }
+
+    #[test]
+    fn test_select_directory_toggles_subtree() {
+        let mut state = State::new(
+            vec![
+                entry("crate", 0, true),
+                entry("crate/src", 1, true),
+                entry("crate/src/app.rs", 2, false),
+                entry("crate/src/widgets", 2, true),
+                entry("crate/src/widgets/options.rs", 3, false),
+                entry("crate/README.md", 1, false),
+            ],
+            Keymap::default(),
+        );
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        let down = KeyEvent::new(KeyCode::Char('j'), KeyModifiers::NONE);
+
+        // include the readme, then the whole src directory
+        for _ in 0..5 {
+            state.process_key(down);
+        }
+        state.process_key(enter);
+        state.list_state.select(Some(1));
+        state.process_key(enter);
+
+        let included: Vec<&str> = state
+            .get_included_entries()
+            .iter()
+            .map(|entry| entry.display_path.as_str())
+            .collect();
+        assert_eq!(
+            included,
+            [
+                "crate/src/app.rs",
+                "crate/src/widgets/options.rs",
+                "crate/README.md"
+            ]
+        );
+
+        // excluding one file leaves its ancestors partially selected
+        state.list_state.select(Some(2));
+        state.process_key(enter);
+        assert_eq!(state.selection(0), Selection::Partial);
+        assert_eq!(state.selection(1), Selection::Partial);
+        assert_eq!(state.selection(3), Selection::All);
+
+        // a partially selected directory is included entirely, then excluded entirely
+        state.list_state.select(Some(1));
+        state.process_key(enter);
+        assert_eq!(state.selection(1), Selection::All);
+        state.process_key(enter);
+        assert_eq!(state.selection(1), Selection::None);
+        assert_eq!(state.selection(0), Selection::Partial);
+    }
+
+    #[test]
+    fn test_roots_are_named_by_label() {
+        assert_eq!(entry("/src/a/lib", 0, true).name(), "/src/a/lib");
+        assert_eq!(entry("/src/a/lib/mod.rs", 1, false).name(), "mod.rs");
+    }
+
+    #[test]
+    fn test_toggle_lines() {
+        let mut state = State::new(
+            vec![entry("crate", 0, true), entry("crate/app.rs", 1, false)],
+            Keymap::default(),
+        );
+        assert!(state.toggle_lines(1, 10..=20));
+        assert!(state.toggle_lines(1, 21..=25));
+        assert!(state.toggle_lines(1, 40..=40));
+        assert_eq!(state.file_list[1].line_ranges, vec![10..=25, 40..=40]);
+        assert_eq!(state.selection(0), Selection::All);
+        // lines that are all included already are left out again
+        assert!(!state.toggle_lines(1, 12..=15));
+        assert_eq!(
+            state.file_list[1].line_ranges,
+            vec![10..=11, 16..=25, 40..=40]
+        );
+        assert!(!state.toggle_lines(1, 40..=40));
+        // some of these lines aren't, so they're all included
+        assert!(state.toggle_lines(1, 10..=25));
+        assert_eq!(state.file_list[1].line_ranges, vec![10..=25]);
+        assert!(!state.toggle_lines(1, 10..=25));
+        assert!(state.file_list[1].excluded);
+        assert_eq!(state.selection(0), Selection::None);
+    }
+
+    #[test]
+    fn test_search_jumps_between_matches() {
+        let mut state = State::new(
+            vec![
+                entry("crate", 0, true),
+                entry("crate/src", 1, true),
+                entry("crate/src/app.rs", 2, false),
+                entry("crate/src/widgets", 2, true),
+                entry("crate/src/widgets/options.rs", 3, false),
+                entry("crate/README.md", 1, false),
+            ],
+            Keymap::default(),
+        );
+        state.set_expanded(3, false);
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        let esc = KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+
+        // typing selects the first match, expanding the collapsed directory it is in
+        for c in "/rs".chars() {
+            state.process_key(key(c));
+        }
+        assert_eq!(state.get_selected_idx(), Some(2));
+        assert_eq!(state.search_matches(), [2, 4]);
+        state.process_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        state.process_key(key('n'));
+        assert_eq!(state.get_selected_idx(), Some(4));
+        state.process_key(key('n'));
+        assert_eq!(state.get_selected_idx(), Some(2));
+        state.process_key(key('N'));
+        assert_eq!(state.get_selected_idx(), Some(4));
+        state.process_key(esc);
+        assert!(state.search.is_none());
+
+        // cancelling while typing goes back to where the search started
+        for c in "/READ".chars() {
+            state.process_key(key(c));
+        }
+        assert_eq!(state.get_selected_idx(), Some(5));
+        state.process_key(esc);
+        assert_eq!(state.get_selected_idx(), Some(4));
+        assert!(state.search.is_none());
+    }
+
+    #[test]
+    fn test_relist_keeps_state() {
+        let mut state = State::new(
+            vec![
+                entry("crate", 0, true),
+                entry("crate/a.rs", 1, false),
+                entry("crate/c.rs", 1, false),
+                entry("crate/d.rs", 1, false),
+            ],
+            Keymap::default(),
+        );
+        state.file_list[2].excluded = false;
+        state.file_list[3].excluded = false;
+        state.list_state.select(Some(3));
+
+        // b.rs was created and c.rs deleted, d.rs keeps its state and the cursor
+        state.relist(
+            0,
+            vec![(
+                PathBuf::from("crate"),
+                vec![
+                    entry("crate/a.rs", 1, false),
+                    entry("crate/b.rs", 1, false),
+                    entry("crate/d.rs", 1, false),
+                ],
+            )],
+        );
+        let paths: Vec<&str> = state
+            .file_list
+            .iter()
+            .map(|entry| entry.display_path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "crate",
+                "crate/a.rs",
+                "crate/b.rs",
+                "crate/c.rs",
+                "crate/d.rs"
+            ]
+        );
+        assert!(state.file_list[3].deleted);
+        assert_eq!(state.get_selected_idx(), Some(4));
+        assert_eq!(state.get_included_entries().len(), 1);
+    }
+
+    #[test]
+    fn test_scanned_entries_are_merged_in_order() {
+        let mut unloaded = entry("crate/a", 1, true);
+        unloaded.entry_type = EntryType::Directory(DirectoryState {
+            expanded: false,
+            loaded: false,
+        });
+        let mut state = State::new(
+            vec![
+                entry("crate", 0, true),
+                unloaded,
+                entry("crate/z.rs", 1, false),
+            ],
+            Keymap::default(),
+        );
+        state.list_state.select(Some(2));
+
+        state.add_batch(Batch {
+            root: "crate".to_owned(),
+            entries: vec![
+                entry("crate/a", 1, true),
+                entry("crate/a/b.rs", 2, false),
+                entry("crate/a/c", 2, true),
+                entry("crate/a/c/d.rs", 3, false),
+                entry("crate/y.rs", 1, false),
+            ],
+            completed: vec!["crate/a/c".to_owned(), "crate/a".to_owned()],
+        });
+        let paths: Vec<&str> = state
+            .file_list
+            .iter()
+            .map(|entry| entry.display_path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "crate",
+                "crate/a",
+                "crate/a/b.rs",
+                "crate/a/c",
+                "crate/a/c/d.rs",
+                "crate/y.rs",
+                "crate/z.rs"
+            ]
+        );
+        // the existing collapsed directory keeps its state and the cursor stays put
+        assert_eq!(state.visible_entries(), [0, 1, 5, 6]);
+        assert_eq!(state.get_selected_idx(), Some(6));
+        assert!(matches!(
+            state.file_list[1].entry_type,
+            EntryType::Directory(DirectoryState { loaded: true, .. })
+        ));
+
+        // expanding and collapsing updates the cached visible entries in place
+        state.expand(1);
+        assert_eq!(state.visible_entries(), [0, 1, 2, 3, 4, 5, 6]);
+        state.set_expanded(3, false);
+        assert_eq!(state.visible_entries(), [0, 1, 2, 3, 5, 6]);
+        assert_eq!(state.visible_entries(), state.visible_in(0..7));
+    }
+
+    #[test]
+    fn test_toggled_directory_applies_to_scanned_files() {
+        let mut unloaded = entry("crate/a", 1, true);
+        unloaded.entry_type = EntryType::Directory(DirectoryState {
+            expanded: false,
+            loaded: false,
+        });
+        let mut state = State::new(vec![entry("crate", 0, true), unloaded], Keymap::default());
+        state.toggle_directory(1);
+
+        state.add_batch(Batch {
+            root: "crate".to_owned(),
+            entries: vec![
+                entry("crate/a", 1, true),
+                entry("crate/a/b.rs", 2, false),
+                entry("crate/a/c", 2, true),
+                entry("crate/a/c/d.rs", 3, false),
+                entry("crate/y.rs", 1, false),
+            ],
+            completed: vec!["crate/a/c".to_owned(), "crate/a".to_owned()],
+        });
+        let included: Vec<&str> = state
+            .get_included_entries()
+            .iter()
+            .map(|entry| entry.display_path.as_str())
+            .collect();
+        assert_eq!(included, ["crate/a/b.rs", "crate/a/c/d.rs"]);
+        assert_eq!(state.selection(1), Selection::All);
+
+        // toggling it again excludes what the scan added
+        state.toggle_directory(1);
+        assert!(state.get_included_entries().is_empty());
+    }
 }
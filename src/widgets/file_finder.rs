@@ -0,0 +1,141 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::widgets::ListState;
+
+use crate::{fuzzy, widgets::file_browser};
+
+const MAX_RESULTS: usize = 200;
+// score added for the most recently used file, less for older ones
+const RECENCY_BONUS: i64 = 40;
+
+// Popup for finding files in the tree by fuzzy matching their paths
+pub struct State {
+    pub query: String,
+    // indices into file_list with the matched char positions of their path
+    pub results: Vec<(usize, Vec<usize>)>,
+    pub list_state: ListState,
+    // path of the selected file, indices go stale when the tree changes
+    selected_path: Option<String>,
+}
+
+pub enum Outcome {
+    Pending,
+    Cancelled,
+    // include or exclude the file, keeping the finder open
+    Toggle(usize),
+    // show the file in the tree
+    Jump(usize),
+}
+
+impl State {
+    pub fn new(browser: &file_browser::State) -> Self {
+        let mut state = Self {
+            query: String::new(),
+            results: Vec::new(),
+            list_state: ListState::default(),
+            selected_path: None,
+        };
+        state.update_results(browser);
+        state
+    }
+
+    // called when the tree changed, e.g. on disk or as the scan goes on, the results hold
+    // indices into it. The same file stays selected if it still matches
+    pub fn update_results(&mut self, browser: &file_browser::State) {
+        let mut scored: Vec<(i64, usize, Vec<usize>)> = browser
+            .file_list
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_file() && !entry.deleted)
+            .filter_map(|(idx, entry)| {
+                let found = fuzzy::fuzzy_match(&self.query, browser.mention_path(entry))?;
+                let recency = browser
+                    .recency(idx)
+                    .map_or(0, |age| (RECENCY_BONUS - 4 * age as i64).max(0));
+                Some((found.score + recency, idx, found.positions))
+            })
+            .collect();
+        // best first, ties keep the tree order
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        scored.truncate(MAX_RESULTS);
+        self.results = scored
+            .into_iter()
+            .map(|(_, idx, positions)| (idx, positions))
+            .collect();
+        let offset = self.selected_path.as_ref().and_then(|path| {
+            self.results
+                .iter()
+                .position(|(idx, _)| &browser.file_list[*idx].path == path)
+        });
+        self.select(offset.or((!self.results.is_empty()).then_some(0)), browser);
+    }
+
+    // a new query starts at the best match
+    fn search(&mut self, browser: &file_browser::State) {
+        self.selected_path = None;
+        self.update_results(browser);
+    }
+
+    pub fn process_key(&mut self, input: KeyEvent, browser: &file_browser::State) -> Outcome {
+        let selected = self
+            .list_state
+            .selected()
+            .and_then(|offset| self.results.get(offset))
+            .map(|(idx, _)| *idx);
+        match input.code {
+            KeyCode::Esc => return Outcome::Cancelled,
+            KeyCode::Enter => {
+                return match selected {
+                    Some(idx) => Outcome::Jump(idx),
+                    None => Outcome::Cancelled,
+                }
+            }
+            KeyCode::Tab => {
+                if let Some(idx) = selected {
+                    self.select_next(browser);
+                    return Outcome::Toggle(idx);
+                }
+            }
+            KeyCode::Up => self.select_previous(browser),
+            KeyCode::Down => self.select_next(browser),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.search(browser);
+            }
+            KeyCode::Char(c)
+                if !input
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                self.query.push(c);
+                self.search(browser);
+            }
+            _ => {}
+        }
+        Outcome::Pending
+    }
+
+    pub fn process_paste(&mut self, text: &str, browser: &file_browser::State) {
+        self.query.push_str(text.trim());
+        self.search(browser);
+    }
+
+    fn select(&mut self, offset: Option<usize>, browser: &file_browser::State) {
+        self.list_state.select(offset);
+        self.selected_path = offset
+            .and_then(|offset| self.results.get(offset))
+            .map(|(idx, _)| browser.file_list[*idx].path.clone());
+    }
+
+    fn select_previous(&mut self, browser: &file_browser::State) {
+        if let Some(offset) = self.list_state.selected() {
+            self.select(Some(offset.saturating_sub(1)), browser);
+        }
+    }
+
+    fn select_next(&mut self, browser: &file_browser::State) {
+        if let Some(offset) = self.list_state.selected() {
+            let last = self.results.len().saturating_sub(1);
+            self.select(Some((offset + 1).min(last)), browser);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::widgets::ListState;
+
+use crate::input::Keymap;
+
+// Reverse incremental search through the prompt history
+pub struct State {
+    // oldest first, as stored in the history
+    entries: Vec<String>,
+    pub query: String,
+    // indices into entries of the prompts containing the query, newest first
+    matches: Vec<usize>,
+    pub list_state: ListState,
+    keys: Keymap,
+}
+
+pub enum Outcome {
+    Pending,
+    Cancelled,
+    Accept(String),
+}
+
+impl State {
+    pub fn new(entries: Vec<String>, keys: Keymap) -> Self {
+        let mut state = Self {
+            entries,
+            query: String::new(),
+            matches: Vec::new(),
+            list_state: ListState::default(),
+            keys,
+        };
+        state.update_matches();
+        state
+    }
+
+    pub fn matches(&self) -> impl Iterator<Item = &str> {
+        self.matches.iter().map(|&idx| self.entries[idx].as_str())
+    }
+
+    fn update_matches(&mut self) {
+        let query = self.query.to_lowercase();
+        self.matches = (0..self.entries.len())
+            .rev()
+            .filter(|&idx| self.entries[idx].to_lowercase().contains(&query))
+            .collect();
+        self.list_state
+            .select((!self.matches.is_empty()).then_some(0));
+    }
+
+    pub fn process_key(&mut self, input: KeyEvent) -> Outcome {
+        let selected = self.list_state.selected();
+        if input.code == KeyCode::Esc {
+            return Outcome::Cancelled;
+        }
+        if self.keys.select.matches(&input) {
+            return match selected.and_then(|offset| self.matches.get(offset)) {
+                Some(&idx) => Outcome::Accept(self.entries[idx].clone()),
+                None => Outcome::Cancelled,
+            };
+        }
+        // pressing the search key again moves to the next older match, like in a shell
+        if self.keys.history_search.matches(&input) || input.code == KeyCode::Down {
+            if let Some(offset) = selected {
+                let last = self.matches.len().saturating_sub(1);
+                self.list_state.select(Some((offset + 1).min(last)));
+            }
+            return Outcome::Pending;
+        }
+        match input.code {
+            KeyCode::Up => {
+                if let Some(offset) = selected {
+                    self.list_state.select(Some(offset.saturating_sub(1)));
+                }
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.update_matches();
+            }
+            KeyCode::Char(c)
+                if !input
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                self.query.push(c);
+                self.update_matches();
+            }
+            _ => {}
+        }
+        Outcome::Pending
+    }
+
+    pub fn process_paste(&mut self, text: &str) {
+        self.query.push_str(&text.replace(['\r', '\n'], " "));
+        self.update_matches();
+    }
+}
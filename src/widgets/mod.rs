@@ -1,3 +1,8 @@
+pub mod completion;
 pub mod file_browser;
+pub mod file_finder;
+pub mod history_search;
 pub mod options;
+pub mod preview;
 pub mod prompt_editor;
+pub mod template_picker;
@@ -1,15 +1,17 @@
-use crate::input::{Action, InputHandler};
+use crate::input::{Action, InputHandler, Keymap};
 
-use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::event::KeyEvent;
 
 pub struct State {
     selected_option: AppOption,
+    keys: Keymap,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(keys: Keymap) -> Self {
         Self {
             selected_option: AppOption::Send,
+            keys,
         }
     }
 
@@ -18,31 +20,22 @@ impl State {
     }
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl InputHandler for State {
     fn process_key(&mut self, input: KeyEvent) -> Option<Action> {
-        match input.code {
-            KeyCode::Char('h') => {
-                self.selected_option = AppOption::Send;
-                None
-            }
-            KeyCode::Char('l') => {
-                self.selected_option = AppOption::Quit;
-                None
-            }
-            KeyCode::Enter => {
-                // Handle the selected option
-                match self.selected_option {
-                    AppOption::Send => Some(Action::Send),
-                    AppOption::Quit => Some(Action::Quit),
-                }
+        if self.keys.left.matches(&input) {
+            self.selected_option = AppOption::Send;
+            None
+        } else if self.keys.right.matches(&input) {
+            self.selected_option = AppOption::Quit;
+            None
+        } else if self.keys.select.matches(&input) {
+            // Handle the selected option
+            match self.selected_option {
+                AppOption::Send => Some(Action::Send),
+                AppOption::Quit => Some(Action::Quit),
             }
-            _ => None,
+        } else {
+            None
         }
     }
 
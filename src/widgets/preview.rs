@@ -0,0 +1,253 @@
+use std::{
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::text::Line;
+
+use crate::{
+    file_text,
+    highlight::highlight,
+    input::{Action, InputHandler, Keymap},
+    theme::Theme,
+    widgets::file_browser::TreeEntry,
+};
+
+// larger files are only described, highlighting them would take too long
+const MAX_PREVIEW_SIZE: u64 = 1024 * 1024;
+// how often the file shown is checked for changes on disk
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct State {
+    // the file shown and when it was modified, it's read again once that changes
+    shown: Option<(String, Option<SystemTime>)>,
+    // when the file shown was last checked for changes
+    checked: Instant,
+    // the request the file being read and highlighted in the background was sent with,
+    // what the loader sends back for earlier ones is dropped
+    loading: Option<usize>,
+    requests: usize,
+    loader: Loader,
+    pub content: Content,
+    // line the cursor is on, the view scrolls to keep it visible
+    pub cursor: usize,
+    // where the lines being selected start, the selection goes on to the cursor
+    pub anchor: Option<usize>,
+    pub scroll: usize,
+    view_height: usize,
+    keys: Keymap,
+}
+
+// The thread that reads and highlights the files to preview, one at a time. Only the
+// latest request waits in the queue, so moving quickly through the tree doesn't leave a
+// backlog of files nobody looks at any more
+struct Loader {
+    requests: Sender<Request>,
+    // the other end of the queue, to take back a request the loader hasn't started on
+    pending: Receiver<Request>,
+    loaded: Receiver<(usize, Content)>,
+}
+
+struct Request {
+    id: usize,
+    path: PathBuf,
+    display_path: String,
+}
+
+impl Loader {
+    fn start(theme: Theme) -> Self {
+        let (requests, pending) = bounded::<Request>(1);
+        let (sender, loaded) = unbounded();
+        let queue = pending.clone();
+        // the thread ends once the state, and with it the sending end, is dropped
+        thread::spawn(move || {
+            for request in queue {
+                let content = load(&request.path, &request.display_path, &theme);
+                if sender.send((request.id, content)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            requests,
+            pending,
+            loaded,
+        }
+    }
+
+    fn request(&self, request: Request) {
+        while self.pending.try_recv().is_ok() {}
+        let _ = self.requests.try_send(request);
+    }
+}
+
+pub enum Content {
+    Lines(Vec<Line<'static>>),
+    // shown in place of the file, e.g. for directories and binary files
+    Note(String),
+}
+
+impl State {
+    pub fn new(keys: Keymap, theme: Theme) -> Self {
+        Self {
+            shown: None,
+            checked: Instant::now(),
+            loading: None,
+            requests: 0,
+            loader: Loader::start(theme),
+            content: Content::Note("Select a file to preview it".to_owned()),
+            cursor: 0,
+            anchor: None,
+            scroll: 0,
+            view_height: 1,
+            keys,
+        }
+    }
+
+    // show the entry selected in the file browser, the position in the file is kept
+    // unless another file is selected. Called every frame, so files are only read when
+    // the selection changes or they changed on disk, and never on the ui thread
+    pub fn show(&mut self, entry: Option<&TreeEntry>) {
+        self.receive();
+        let Some(entry) = entry else {
+            return;
+        };
+        if !entry.is_file() {
+            self.shown = None;
+            self.loading = None;
+            self.content = Content::Note(format!("{} is a directory", entry.display_path));
+            return;
+        }
+        let same_file = self
+            .shown
+            .as_ref()
+            .is_some_and(|(path, _)| *path == entry.path);
+        if same_file && self.checked.elapsed() < RECHECK_INTERVAL {
+            return;
+        }
+        self.checked = Instant::now();
+        let modified = fs::metadata(&entry.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if same_file
+            && self
+                .shown
+                .as_ref()
+                .is_some_and(|(_, shown)| *shown == modified)
+        {
+            return;
+        }
+        if !same_file {
+            self.cursor = 0;
+            self.anchor = None;
+            self.scroll = 0;
+            self.content = Content::Note(format!("Loading {}", entry.display_path));
+        }
+        self.shown = Some((entry.path.clone(), modified));
+
+        self.requests += 1;
+        self.loader.request(Request {
+            id: self.requests,
+            path: PathBuf::from(&entry.path),
+            display_path: entry.display_path.clone(),
+        });
+        self.loading = Some(self.requests);
+    }
+
+    // take the file loaded in the background once it's ready
+    fn receive(&mut self) {
+        while let Ok((id, content)) = self.loader.loaded.try_recv() {
+            if self.loading != Some(id) {
+                continue;
+            }
+            self.loading = None;
+            self.content = content;
+            let last = self.line_count().saturating_sub(1);
+            self.cursor = self.cursor.min(last);
+            self.anchor = self.anchor.map(|anchor| anchor.min(last));
+        }
+    }
+
+    // the 1-based lines selected, or the one the cursor is on, the selection ends
+    pub fn take_selected_lines(&mut self) -> Option<RangeInclusive<usize>> {
+        if self.line_count() == 0 {
+            return None;
+        }
+        let anchor = self.anchor.take().unwrap_or(self.cursor);
+        Some(anchor.min(self.cursor) + 1..=anchor.max(self.cursor) + 1)
+    }
+
+    pub fn line_count(&self) -> usize {
+        match &self.content {
+            Content::Lines(lines) => lines.len(),
+            Content::Note(_) => 0,
+        }
+    }
+
+    pub fn update_view(&mut self, height: usize) {
+        self.view_height = height.max(1);
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + self.view_height {
+            self.scroll = self.cursor + 1 - self.view_height;
+        }
+        self.scroll = self
+            .scroll
+            .min(self.line_count().saturating_sub(self.view_height));
+    }
+}
+
+fn load(path: &Path, display_path: &str, theme: &Theme) -> Content {
+    let size = fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    if size > MAX_PREVIEW_SIZE {
+        return Content::Note(format!(
+            "{} is too large to preview ({} KB)",
+            display_path,
+            size / 1024
+        ));
+    }
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => return Content::Note(format!("Failed to read {}: {}", display_path, err)),
+    };
+    match file_text::decode(&bytes) {
+        Some(contents) => Content::Lines(highlight(&contents.text, path, theme)),
+        None => Content::Note(format!("{} is a binary file", display_path)),
+    }
+}
+
+impl InputHandler for State {
+    fn process_key(&mut self, input: KeyEvent) -> Option<Action> {
+        let last = self.line_count().saturating_sub(1);
+        if self.keys.select_lines.matches(&input) {
+            self.anchor = match self.anchor {
+                Some(_) => None,
+                None => Some(self.cursor),
+            };
+        } else if self.anchor.is_some() && input.code == KeyCode::Esc {
+            self.anchor = None;
+        } else if self.keys.up.matches(&input) {
+            self.cursor = self.cursor.saturating_sub(1);
+        } else if self.keys.down.matches(&input) {
+            self.cursor = (self.cursor + 1).min(last);
+        } else {
+            match input.code {
+                KeyCode::PageUp => self.cursor = self.cursor.saturating_sub(self.view_height),
+                KeyCode::PageDown => self.cursor = (self.cursor + self.view_height).min(last),
+                KeyCode::Home => self.cursor = 0,
+                KeyCode::End => self.cursor = last,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn process_tick(&mut self) {}
+}
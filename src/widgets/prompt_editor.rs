@@ -1,47 +1,376 @@
-use crossterm::event::KeyCode;
+use std::ops::Range;
 
-use crate::input::InputHandler;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::{
+    buffer::{EditKind, TextBuffer, UndoStack},
+    config::EditorConfig,
+    input::{Action, InputHandler, Keymap},
+    vim::{Mode, Outcome, Vim},
+};
 
 pub struct State {
-    prompt_text: String,
+    buffer: TextBuffer,
+    undo: UndoStack,
+    // first wrapped row shown in the pane
+    scroll: usize,
+    // size of the pane's text area the last time it was drawn
+    view_width: usize,
+    view_height: usize,
+    // large pastes, shown in the text as a marker and expanded when the query is built.
+    // Blocks whose marker was deleted are kept so undo can bring them back
+    pasted_blocks: Vec<String>,
+    // modal editing, only when enabled in the config
+    vim: Option<Vim>,
+    keys: Keymap,
+    config: EditorConfig,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(keys: Keymap, config: EditorConfig) -> Self {
         Self {
-            prompt_text: String::new(),
+            buffer: TextBuffer::default(),
+            undo: UndoStack::default(),
+            scroll: 0,
+            view_width: 80,
+            view_height: 20,
+            pasted_blocks: Vec::new(),
+            vim: config.vim_mode.then(Vim::default),
+            keys,
+            config,
         }
     }
 
+    // the current vim mode, None when modal editing is off
+    pub fn mode(&self) -> Option<Mode> {
+        self.vim.as_ref().map(Vim::mode)
+    }
+
+    // the highlighted text, vim's visual selection includes the character under the cursor
+    pub fn selection(&self) -> Option<Range<usize>> {
+        match &self.vim {
+            Some(vim) if vim.mode() == Mode::Visual => vim.selection(&self.buffer),
+            _ => self.buffer.selection(),
+        }
+    }
+
+    pub fn selected_text(&self) -> &str {
+        self.selection()
+            .map_or("", |range| &self.buffer.text()[range])
+    }
+
+    // insert at the cursor as one undoable edit, replacing the selection if there is one
+    pub fn insert_text(&mut self, text: &str) {
+        let cursor = self.buffer.cursor();
+        let range = self.selection().unwrap_or(cursor..cursor);
+        self.replace_text(range, text);
+    }
+
+    pub fn replace_text(&mut self, range: Range<usize>, text: &str) {
+        self.leave_visual();
+        self.undo.edit(&mut self.buffer, EditKind::Other, |buffer| {
+            buffer.replace_range(range, text);
+        });
+    }
+
     pub fn get_display_text(&self) -> &str {
-        &self.prompt_text
+        self.buffer.text()
+    }
+
+    // the prompt as it is sent, with pasted block markers replaced by their contents
+    pub fn get_query_text(&self) -> String {
+        let text = self.buffer.text();
+        let mut query = String::with_capacity(text.len());
+        let mut last = 0;
+        for atom in self.buffer.atoms() {
+            query.push_str(&text[last..atom.range.start]);
+            query.push_str(&self.pasted_blocks[atom.id]);
+            last = atom.range.end;
+        }
+        query.push_str(&text[last..]);
+        query
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.leave_visual();
+        self.undo.edit(&mut self.buffer, EditKind::Other, |buffer| {
+            buffer.set_text(text)
+        });
+    }
+
+    // the visual selection doesn't survive changes made outside vim, its anchor could be
+    // past the end of the new text
+    fn leave_visual(&mut self) {
+        if let Some(vim) = &mut self.vim {
+            vim.leave_visual();
+        }
+    }
+
+    // called when focus leaves the editor, so typing after returning is undone separately
+    pub fn seal_history(&mut self) {
+        self.undo.seal();
+    }
+
+    // whether the cursor is on the first or last wrapped row, where Up and Down recall history
+    pub fn on_first_row(&self) -> bool {
+        let rows = self.buffer.wrap(self.view_width);
+        self.buffer.cursor_row_column(&rows).0 == 0
+    }
+
+    pub fn on_last_row(&self) -> bool {
+        let rows = self.buffer.wrap(self.view_width);
+        self.buffer.cursor_row_column(&rows).0 + 1 >= rows.len()
+    }
+
+    pub fn buffer(&self) -> &TextBuffer {
+        &self.buffer
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    // record the size of the text area and scroll so the cursor stays visible
+    pub fn update_view(&mut self, width: usize, height: usize) {
+        self.view_width = width.max(1);
+        self.view_height = height.max(1);
+
+        let rows = self.buffer.wrap(self.view_width);
+        let (row, _) = self.buffer.cursor_row_column(&rows);
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + self.view_height {
+            self.scroll = row + 1 - self.view_height;
+        }
+        self.scroll = self.scroll.min(rows.len().saturating_sub(self.view_height));
     }
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self::new()
+impl State {
+    fn process_movement(&mut self, input: KeyEvent, select: bool, ctrl: bool, alt: bool) {
+        // typing after the cursor moved is undone separately
+        self.undo.seal();
+        let buffer = &mut self.buffer;
+
+        match input.code {
+            KeyCode::Char('a') if ctrl => buffer.select_all(),
+            KeyCode::Char('b') if alt => buffer.move_word_left(select),
+            KeyCode::Char('f') if alt => buffer.move_word_right(select),
+            KeyCode::Left if ctrl || alt => buffer.move_word_left(select),
+            KeyCode::Right if ctrl || alt => buffer.move_word_right(select),
+            KeyCode::Left => buffer.move_left(select),
+            KeyCode::Right => buffer.move_right(select),
+            KeyCode::Up => buffer.move_rows(-1, self.view_width, select),
+            KeyCode::Down => buffer.move_rows(1, self.view_width, select),
+            KeyCode::PageUp => {
+                buffer.move_rows(-(self.view_height as isize), self.view_width, select)
+            }
+            KeyCode::PageDown => {
+                buffer.move_rows(self.view_height as isize, self.view_width, select)
+            }
+            KeyCode::Home if ctrl => buffer.move_to(0, select),
+            KeyCode::End if ctrl => buffer.move_to(buffer.text().len(), select),
+            KeyCode::Home => buffer.move_line_start(select),
+            KeyCode::End => buffer.move_line_end(select),
+            KeyCode::Esc => buffer.clear_selection(),
+            _ => {}
+        }
     }
 }
 
+// the placeholder shown in the editor for a collapsed paste
+fn paste_marker(idx: usize, block: &str) -> String {
+    format!(
+        "[pasted block #{}: {} lines]",
+        idx + 1,
+        block.lines().count()
+    )
+}
+
 impl InputHandler for State {
-    fn process_key(&mut self, input: crossterm::event::KeyEvent) -> Option<crate::input::Action> {
-        match input.code {
-            KeyCode::Char(c) => {
-                self.prompt_text.push(c);
-                None
+    fn process_key(&mut self, input: KeyEvent) -> Option<Action> {
+        let select = input.modifiers.contains(KeyModifiers::SHIFT);
+        let ctrl = input.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = input.modifiers.contains(KeyModifiers::ALT);
+
+        if self.keys.undo.matches(&input) {
+            self.leave_visual();
+            self.undo.undo(&mut self.buffer);
+            return None;
+        }
+        if self.keys.redo.matches(&input) {
+            self.leave_visual();
+            self.undo.redo(&mut self.buffer);
+            return None;
+        }
+        if self.keys.external_editor.matches(&input) {
+            return Some(Action::EditPrompt);
+        }
+
+        if let Some(vim) = &mut self.vim {
+            match vim.mode() {
+                Mode::Insert if input.code == KeyCode::Esc => {
+                    vim.leave_insert(input, &mut self.buffer);
+                    return None;
+                }
+                Mode::Insert => vim.record(input),
+                _ => {
+                    if let Outcome::Repeat(keys, count) =
+                        vim.process_key(input, &mut self.buffer, &mut self.undo)
+                    {
+                        for _ in 0..count {
+                            for key in &keys {
+                                self.process_key(*key);
+                            }
+                        }
+                        if let Some(vim) = &mut self.vim {
+                            vim.finish_repeat();
+                        }
+                    }
+                    return None;
+                }
             }
-            KeyCode::Backspace => {
-                self.prompt_text.pop();
-                None
+        }
+
+        let (kind, edit): (EditKind, fn(&mut TextBuffer)) = match input.code {
+            KeyCode::Char('w') if ctrl => (EditKind::Other, TextBuffer::delete_word_back),
+            KeyCode::Char(c) if !ctrl && !alt => {
+                let kind = match c.is_whitespace() {
+                    true => EditKind::Whitespace,
+                    false => EditKind::Typing,
+                };
+                self.undo.edit(&mut self.buffer, kind, |buffer| {
+                    buffer.insert_str(c.encode_utf8(&mut [0; 4]))
+                });
+                return None;
             }
-            KeyCode::Enter => {
-                self.prompt_text.push('\n');
-                None
+            KeyCode::Tab => (EditKind::Whitespace, |buffer| buffer.insert_str("\t")),
+            KeyCode::Enter => (EditKind::Other, |buffer| buffer.insert_str("\n")),
+            KeyCode::Backspace if ctrl || alt => (EditKind::Other, TextBuffer::delete_word_back),
+            KeyCode::Backspace => (EditKind::Deleting, TextBuffer::backspace),
+            KeyCode::Delete => (EditKind::Deleting, TextBuffer::delete),
+            _ => {
+                self.process_movement(input, select, ctrl, alt);
+                return None;
             }
-            _ => None,
+        };
+        self.undo.edit(&mut self.buffer, kind, edit);
+        None
+    }
+
+    fn process_paste(&mut self, text: &str) -> Option<Action> {
+        // terminals send carriage returns for newlines
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+
+        let limit = self.config.collapse_paste_lines;
+        if limit > 0 && text.lines().count() > limit {
+            let id = self.pasted_blocks.len();
+            let marker = paste_marker(id, &text);
+            self.pasted_blocks.push(text);
+            self.undo.edit(&mut self.buffer, EditKind::Other, |buffer| {
+                buffer.insert_atom(&marker, id)
+            });
+        } else {
+            self.undo.edit(&mut self.buffer, EditKind::Other, |buffer| {
+                buffer.insert_str(&text)
+            });
         }
+        None
     }
 
     fn process_tick(&mut self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_large_pastes_are_collapsed() {
+        let mut state = State::new(
+            Keymap::default(),
+            EditorConfig {
+                collapse_paste_lines: 2,
+                vim_mode: false,
+            },
+        );
+        state.process_paste("see:\r\n");
+        state.process_paste("line 1\n\tline 2\nline 3");
+        assert_eq!(state.get_display_text(), "see:\n[pasted block #1: 3 lines]");
+        assert_eq!(state.get_query_text(), "see:\nline 1\n\tline 2\nline 3");
+    }
+
+    #[test]
+    fn test_paste_markers_are_edited_whole() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let mut state = State::new(
+            Keymap::default(),
+            EditorConfig {
+                collapse_paste_lines: 1,
+                vim_mode: true,
+            },
+        );
+        state.process_key(key(KeyCode::Char('i')));
+        state.process_paste("a\nb");
+        state.process_key(key(KeyCode::Char('!')));
+        let marker = "[pasted block #1: 2 lines]";
+
+        // typing inside the marker goes after it
+        state.process_key(key(KeyCode::Left));
+        state.process_key(key(KeyCode::Left));
+        assert_eq!(state.buffer().cursor(), 0);
+        state.process_key(key(KeyCode::Right));
+        state.process_key(key(KeyCode::Char('?')));
+        assert_eq!(state.get_display_text(), format!("{}?!", marker));
+        assert_eq!(state.get_query_text(), "a\nb?!");
+
+        // vim's x takes the whole marker
+        state.process_key(key(KeyCode::Esc));
+        state.process_key(key(KeyCode::Char('0')));
+        state.process_key(key(KeyCode::Char('x')));
+        assert_eq!(state.get_display_text(), "?!");
+        assert_eq!(state.get_query_text(), "?!");
+
+        // undo brings the block back with it, typing the marker doesn't
+        state.process_key(KeyEvent::new(KeyCode::Char('z'), KeyModifiers::CONTROL));
+        assert_eq!(state.get_query_text(), "a\nb?!");
+        state.set_text("");
+        state.process_key(key(KeyCode::Char('i')));
+        for c in marker.chars() {
+            state.process_key(key(KeyCode::Char(c)));
+        }
+        assert_eq!(state.get_query_text(), marker);
+        state.process_key(key(KeyCode::Esc));
+        state.set_text("");
+        state.process_key(key(KeyCode::Char('a')));
+        state.process_paste("a\nb");
+        state.process_key(key(KeyCode::Backspace));
+        assert_eq!(state.get_display_text(), "");
+    }
+
+    #[test]
+    fn test_undo_leaves_visual_mode() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let mut state = State::new(
+            Keymap::default(),
+            EditorConfig {
+                collapse_paste_lines: 0,
+                vim_mode: true,
+            },
+        );
+        state.process_key(key(KeyCode::Char('i')));
+        for c in "hello world".chars() {
+            state.process_key(key(KeyCode::Char(c)));
+        }
+        state.process_key(key(KeyCode::Esc));
+        state.process_key(key(KeyCode::Char('v')));
+        assert!(state.mode() == Some(Mode::Visual));
+        state.process_key(KeyEvent::new(KeyCode::Char('z'), KeyModifiers::CONTROL));
+        assert!(state.mode() == Some(Mode::Normal));
+        assert_eq!(state.selection(), None);
+
+        state.process_key(key(KeyCode::Char('v')));
+        state.set_text("");
+        assert_eq!(state.selection(), None);
+    }
+}
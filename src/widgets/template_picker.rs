@@ -0,0 +1,132 @@
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::widgets::ListState;
+
+use crate::{
+    buffer::TextBuffer,
+    input::Keymap,
+    templates::{self, Segment, Template},
+};
+
+// Popup for choosing a template and answering its {{ask:...}} questions
+pub struct State {
+    templates: Vec<Template>,
+    pub list_state: ListState,
+    stage: Stage,
+    keys: Keymap,
+}
+
+enum Stage {
+    Choosing,
+    Asking {
+        segments: Vec<Segment>,
+        questions: Vec<String>,
+        answers: Vec<String>,
+        answer: TextBuffer,
+    },
+}
+
+pub enum Outcome {
+    Pending,
+    Cancelled,
+    // the chosen template with an answer for each of its questions
+    Insert(Vec<Segment>, Vec<String>),
+}
+
+impl State {
+    pub fn new(templates: Vec<Template>, keys: Keymap) -> Self {
+        let mut list_state = ListState::default();
+        list_state.select(Some(0));
+        Self {
+            templates,
+            list_state,
+            stage: Stage::Choosing,
+            keys,
+        }
+    }
+
+    pub fn templates(&self) -> &[Template] {
+        &self.templates
+    }
+
+    // the question being answered and the answer typed so far
+    pub fn question(&self) -> Option<(&str, &TextBuffer)> {
+        match &self.stage {
+            Stage::Choosing => None,
+            Stage::Asking {
+                questions,
+                answers,
+                answer,
+                ..
+            } => Some((&questions[answers.len()], answer)),
+        }
+    }
+
+    pub fn process_key(&mut self, input: KeyEvent) -> Result<Outcome> {
+        if input.code == KeyCode::Esc {
+            return Ok(Outcome::Cancelled);
+        }
+        match &mut self.stage {
+            Stage::Choosing => {
+                let selected = self.list_state.selected().unwrap_or(0);
+                if self.keys.select.matches(&input) {
+                    let segments = self.templates[selected].parse()?;
+                    let questions = templates::questions(&segments);
+                    if questions.is_empty() {
+                        return Ok(Outcome::Insert(segments, Vec::new()));
+                    }
+                    self.stage = Stage::Asking {
+                        segments,
+                        questions,
+                        answers: Vec::new(),
+                        answer: TextBuffer::default(),
+                    };
+                } else if input.code == KeyCode::Up || self.keys.up.matches(&input) {
+                    self.list_state.select(Some(selected.saturating_sub(1)));
+                } else if input.code == KeyCode::Down || self.keys.down.matches(&input) {
+                    let last = self.templates.len().saturating_sub(1);
+                    self.list_state.select(Some((selected + 1).min(last)));
+                }
+            }
+            Stage::Asking {
+                segments,
+                questions,
+                answers,
+                answer,
+            } => match input.code {
+                KeyCode::Enter => {
+                    answers.push(answer.text().to_owned());
+                    answer.set_text("");
+                    if answers.len() == questions.len() {
+                        return Ok(Outcome::Insert(
+                            std::mem::take(segments),
+                            std::mem::take(answers),
+                        ));
+                    }
+                }
+                KeyCode::Char(c)
+                    if !input
+                        .modifiers
+                        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+                {
+                    answer.insert_str(c.encode_utf8(&mut [0; 4]))
+                }
+                KeyCode::Backspace => answer.backspace(),
+                KeyCode::Delete => answer.delete(),
+                KeyCode::Left => answer.move_left(false),
+                KeyCode::Right => answer.move_right(false),
+                KeyCode::Home => answer.move_line_start(false),
+                KeyCode::End => answer.move_line_end(false),
+                _ => {}
+            },
+        }
+        Ok(Outcome::Pending)
+    }
+
+    // answers are a single line, pasted newlines become spaces
+    pub fn process_paste(&mut self, text: &str) {
+        if let Stage::Asking { answer, .. } = &mut self.stage {
+            answer.insert_str(&text.replace(['\r', '\n'], " "));
+        }
+    }
+}